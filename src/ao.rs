@@ -0,0 +1,142 @@
+use cgmath::{Vector3, Zero};
+
+use crate::{vertices::VERTICES, world::NEIGHBOURS};
+
+// Ambient occlusion of a corner, from 0 when tucked into a corner to 3 when
+// nothing is around it
+pub const OPEN: u8 = 3;
+// Every corner of every face open, for faces nothing is computed for
+pub const NO_OCCLUSION: [[u8; 4]; 6] = [[OPEN; 4]; 6];
+
+// Occlusion of a face corner from the two blocks beside it and the one
+// diagonally across, all in the layer in front of the face. With both sides
+// blocked the corner can't be seen from the diagonal anyway
+pub fn vertex_ao(side_1: bool, side_2: bool, corner: bool) -> u8 {
+    if side_1 && side_2 {
+        0
+    } else {
+        OPEN - side_1 as u8 - side_2 as u8 - corner as u8
+    }
+}
+
+// Occlusion at the four corners of a face of the block at `pos`, in the order
+// of the face's vertices in VERTICES
+pub fn face_ao(occludes: impl Fn(Vector3<i32>) -> bool, pos: Vector3<i32>, face: usize) -> [u8; 4] {
+    let normal = NEIGHBOURS[face];
+    let in_front = pos + normal;
+    std::array::from_fn(|i| {
+        // Steps from the block in front of the face towards the corner
+        let along = VERTICES[face * 4 + i].corner() - normal;
+        let mut sides = [Vector3::zero(); 2];
+        for (side, axis) in sides
+            .iter_mut()
+            .zip((0..3).filter(|axis| along[*axis] != 0))
+        {
+            side[axis] = along[axis];
+        }
+        vertex_ao(
+            occludes(in_front + sides[0]),
+            occludes(in_front + sides[1]),
+            occludes(in_front + along),
+        )
+    })
+}
+
+// Occlusion of every face of a block, with `opaque` telling which blocks cast it. Every block a
+// face corner looks at is within one step of the block, so the 3x3x3 blocks
+// around it are looked up once instead of three times per corner. Faces
+// covered by an opaque block can't be seen and are left open
+pub fn block_ao(opaque: impl Fn(Vector3<i32>) -> bool, pos: Vector3<i32>) -> [[u8; 4]; 6] {
+    let index =
+        |offset: Vector3<i32>| ((offset.z + 1) * 9 + (offset.y + 1) * 3 + offset.x + 1) as usize;
+    let mut around = [false; 27];
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let offset = Vector3::new(x, y, z);
+                around[index(offset)] = opaque(pos + offset);
+            }
+        }
+    }
+    let occludes = |block: Vector3<i32>| around[index(block - pos)];
+    std::array::from_fn(|face| {
+        if occludes(pos + NEIGHBOURS[face]) {
+            [OPEN; 4]
+        } else {
+            face_ao(occludes, pos, face)
+        }
+    })
+}
+
+// Quads are split along the diagonal from their first to their third vertex.
+// When that diagonal is the darker one, splitting along the other keeps a dark
+// corner from being smeared across both triangles
+pub fn flip_quad(ao: [u8; 4]) -> bool {
+    ao[0] + ao[2] < ao[1] + ao[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::DIRT, world::World};
+
+    fn world_ao(world: &World, pos: Vector3<i32>) -> [[u8; 4]; 6] {
+        block_ao(|pos| world.blocks.get(world.get_block(pos)).opaque, pos)
+    }
+
+    #[test]
+    fn corner_configurations() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, false), 2);
+        assert_eq!(vertex_ao(false, true, true), 1);
+        assert_eq!(vertex_ao(true, true, false), 0);
+        assert_eq!(vertex_ao(true, true, true), 0);
+    }
+
+    #[test]
+    fn wall_darkens_the_corners_next_to_it() {
+        // Standing on the ground with a wall to the +x side
+        let mut world = World::new();
+        world.set_block(Vector3::new(0, 0, 0), DIRT);
+        world.set_block(Vector3::new(1, 1, 0), DIRT);
+        world.set_block(Vector3::new(1, 1, 1), DIRT);
+        world.set_block(Vector3::new(1, 1, -1), DIRT);
+
+        let ao = world_ao(&world, Vector3::new(0, 0, 0));
+        // Top face corners on the +x edge touch the wall
+        let top = ao[2];
+        for (i, vertex) in VERTICES[8..12].iter().enumerate() {
+            let expected = if vertex.corner().x > 0 { 1 } else { 3 };
+            assert_eq!(top[i], expected, "corner {:?}", vertex.corner());
+        }
+        assert_eq!(ao[3], [OPEN; 4]);
+    }
+
+    #[test]
+    fn inside_corner() {
+        // Walls on the +x and +z sides meet above the block
+        let mut world = World::new();
+        world.set_block(Vector3::new(0, 0, 0), DIRT);
+        world.set_block(Vector3::new(1, 1, 0), DIRT);
+        world.set_block(Vector3::new(0, 1, 1), DIRT);
+
+        let top = world_ao(&world, Vector3::new(0, 0, 0))[2];
+        for (i, vertex) in VERTICES[8..12].iter().enumerate() {
+            let corner = vertex.corner();
+            let expected = match (corner.x > 0, corner.z > 0) {
+                (true, true) => 0,
+                (false, false) => 3,
+                _ => 2,
+            };
+            assert_eq!(top[i], expected, "corner {:?}", corner);
+        }
+    }
+
+    #[test]
+    fn flips_away_from_a_dark_diagonal() {
+        assert!(!flip_quad([3, 3, 3, 3]));
+        assert!(flip_quad([0, 3, 3, 3]));
+        assert!(!flip_quad([3, 0, 3, 3]));
+    }
+}
@@ -0,0 +1,162 @@
+pub type BlockId = u8;
+
+// Ids of the blocks registered by `BlockRegistry::default`, in order
+pub const AIR: BlockId = 0;
+pub const DIRT: BlockId = 1;
+pub const GRASS: BlockId = 2;
+pub const WATER: BlockId = 3;
+pub const MUD: BlockId = 4;
+
+// How a block's texture is drawn, each kind in its own pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    // Every pixel is drawn
+    Opaque,
+    // Pixels are either drawn or left out entirely, like the gaps in a ladder
+    Cutout,
+    // Blended over what's behind it, like water
+    Translucent,
+}
+
+impl Transparency {
+    // In the order they're drawn
+    pub const ALL: [Transparency; 3] = [
+        Transparency::Opaque,
+        Transparency::Cutout,
+        Transparency::Translucent,
+    ];
+}
+
+// How a block's faces are shaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Material {
+    // Its texture, lit like everything else
+    Plain,
+    // Waves and ripples on top, coloured and see through by how deep it is
+    Water,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockType {
+    pub name: &'static str,
+    // Collides with the player
+    pub solid: bool,
+    // Hides the faces of the blocks behind it
+    pub opaque: bool,
+    // Can be swum in
+    pub liquid: bool,
+    // Can be climbed like a ladder
+    pub climbable: bool,
+    // Multiplier on walking speed while inside or standing on the block
+    pub speed_factor: f32,
+    // Block light level given off, 0 for blocks that don't glow
+    pub light: u8,
+    pub transparency: Transparency,
+    pub material: Material,
+}
+
+impl BlockType {
+    pub const fn solid(name: &'static str) -> Self {
+        Self {
+            name,
+            solid: true,
+            opaque: true,
+            liquid: false,
+            climbable: false,
+            speed_factor: 1.0,
+            light: 0,
+            transparency: Transparency::Opaque,
+            material: Material::Plain,
+        }
+    }
+
+    pub const fn empty(name: &'static str) -> Self {
+        Self {
+            name,
+            solid: false,
+            opaque: false,
+            liquid: false,
+            climbable: false,
+            speed_factor: 1.0,
+            light: 0,
+            transparency: Transparency::Cutout,
+            material: Material::Plain,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockType>,
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let mut registry = Self { blocks: vec![] };
+        registry.register(BlockType::empty("air"));
+        registry.register(BlockType::solid("dirt"));
+        registry.register(BlockType::solid("grass"));
+        registry.register(BlockType {
+            liquid: true,
+            speed_factor: 0.5,
+            transparency: Transparency::Translucent,
+            material: Material::Water,
+            ..BlockType::empty("water")
+        });
+        registry.register(BlockType {
+            speed_factor: 0.5,
+            ..BlockType::solid("mud")
+        });
+        registry.register(BlockType {
+            climbable: true,
+            ..BlockType::empty("ladder")
+        });
+        registry.register(BlockType {
+            speed_factor: 0.25,
+            ..BlockType::empty("cobweb")
+        });
+        registry.register(BlockType {
+            light: 14,
+            ..BlockType::empty("torch")
+        });
+        registry.register(BlockType {
+            liquid: true,
+            speed_factor: 0.3,
+            light: 15,
+            transparency: Transparency::Opaque,
+            ..BlockType::empty("lava")
+        });
+        registry.register(BlockType {
+            light: 15,
+            ..BlockType::solid("glowstone")
+        });
+        registry.register(BlockType {
+            opaque: false,
+            transparency: Transparency::Cutout,
+            ..BlockType::solid("glass")
+        });
+        registry.register(BlockType {
+            opaque: false,
+            transparency: Transparency::Translucent,
+            ..BlockType::solid("stained_glass")
+        });
+        registry
+    }
+}
+
+impl BlockRegistry {
+    pub fn register(&mut self, block: BlockType) -> BlockId {
+        let id = self.blocks.len() as BlockId;
+        self.blocks.push(block);
+        id
+    }
+
+    pub fn get(&self, id: BlockId) -> &BlockType {
+        &self.blocks[id as usize]
+    }
+
+    // In order of their ids
+    pub fn iter(&self) -> impl Iterator<Item = &BlockType> {
+        self.blocks.iter()
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use image::{imageops, ImageFormat, Rgba, RgbaImage};
+
+use crate::{
+    block::{BlockId, BlockRegistry},
+    resource_pack::ResourcePacks,
+};
+
+// Names of the faces as used in texture file names, in the same order as
+// NEIGHBOURS: +x, -x, +y, -y, +z, -z
+pub const FACE_NAMES: [&str; 6] = ["east", "west", "top", "bottom", "south", "north"];
+const SIDE_FACES: [usize; 4] = [0, 1, 4, 5];
+
+// Layer of the texture shown for faces that don't have one
+pub const MISSING_LAYER: u32 = 0;
+// Instances store layers in a byte, which is also the most layers wgpu
+// guarantees a texture array can have
+pub const MAX_LAYERS: usize = 256;
+
+// Collects textures by name and gives each distinct one a layer in a texture
+// array. Every layer ends up the size of the largest texture
+#[derive(Debug)]
+pub struct TexturePacker {
+    layers: Vec<RgbaImage>,
+    by_name: HashMap<String, u32>,
+}
+
+impl Default for TexturePacker {
+    fn default() -> Self {
+        Self {
+            layers: vec![missing_texture(16)],
+            by_name: HashMap::new(),
+        }
+    }
+}
+
+impl TexturePacker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    // Adding a name twice keeps the layer but replaces the texture
+    pub fn add(&mut self, name: &str, image: RgbaImage) -> u32 {
+        if let Some(layer) = self.layer(name) {
+            self.layers[layer as usize] = image;
+            return layer;
+        }
+        let layer = self.layers.len() as u32;
+        self.layers.push(image);
+        self.by_name.insert(name.to_string(), layer);
+        layer
+    }
+
+    // Scales every layer up to the size of the largest texture with nearest
+    // neighbour sampling, so pixel art stays sharp
+    pub fn pack(self) -> Vec<RgbaImage> {
+        let size = self
+            .layers
+            .iter()
+            .skip(1)
+            .map(|layer| layer.width().max(layer.height()))
+            .max()
+            .unwrap_or(16);
+        self.layers
+            .into_iter()
+            .enumerate()
+            .map(|(layer, image)| {
+                if layer == MISSING_LAYER as usize {
+                    missing_texture(size)
+                } else if image.dimensions() == (size, size) {
+                    image
+                } else {
+                    imageops::resize(&image, size, size, imageops::FilterType::Nearest)
+                }
+            })
+            .collect()
+    }
+}
+
+// The layers of a texture array and which one every face of every block uses
+#[derive(Debug)]
+pub struct BlockTextures {
+    faces: Vec<[u32; 6]>,
+    pub layers: Vec<RgbaImage>,
+}
+
+impl BlockTextures {
+    // Loads the textures of every resource pack in order, so a texture in a
+    // later pack replaces the one with the same name in an earlier pack. See
+    // `from_packer` for how they're named
+    pub fn load(packs: &ResourcePacks, blocks: &BlockRegistry) -> Result<Self> {
+        let mut packer = TexturePacker::new();
+        for pack in packs.iter() {
+            for (file, bytes) in pack.textures()? {
+                let Some(name) = file.strip_suffix(".png") else {
+                    continue;
+                };
+                let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png)
+                    .with_context(|| {
+                        format!(
+                            "failed to load texture {} from resource pack {}",
+                            file, pack.manifest.name
+                        )
+                    })?;
+                packer.add(name, image.to_rgba8());
+            }
+        }
+
+        let textures = Self::from_packer(packer, blocks);
+        ensure!(
+            textures.layers.len() <= MAX_LAYERS,
+            "resource packs have {} textures, at most {} are supported",
+            textures.layers.len() - 1,
+            MAX_LAYERS - 1
+        );
+        Ok(textures)
+    }
+
+    // Finds the texture for each face of each block, trying `<block>_<face>`
+    // first, then `<block>_side` for the four sides and finally `<block>`
+    pub fn from_packer(packer: TexturePacker, blocks: &BlockRegistry) -> Self {
+        let faces = blocks
+            .iter()
+            .map(|block| {
+                let mut faces = [MISSING_LAYER; 6];
+                for (face, layer) in faces.iter_mut().enumerate() {
+                    let side = SIDE_FACES
+                        .contains(&face)
+                        .then(|| format!("{}_side", block.name));
+                    *layer = [Some(format!("{}_{}", block.name, FACE_NAMES[face])), side]
+                        .into_iter()
+                        .flatten()
+                        .chain([block.name.to_string()])
+                        .find_map(|name| packer.layer(&name))
+                        .unwrap_or(MISSING_LAYER);
+                }
+                faces
+            })
+            .collect();
+        Self {
+            faces,
+            layers: packer.pack(),
+        }
+    }
+
+    // Layers of every face of every block, indexed by block id
+    pub fn all_faces(&self) -> &[[u32; 6]] {
+        &self.faces
+    }
+
+    pub fn faces(&self, block: BlockId) -> [u32; 6] {
+        self.faces
+            .get(block as usize)
+            .copied()
+            .unwrap_or([MISSING_LAYER; 6])
+    }
+}
+
+// Magenta and black checkerboard
+fn missing_texture(size: u32) -> RgbaImage {
+    let half = (size / 2).max(1);
+    RgbaImage::from_fn(size, size, |x, y| {
+        if (x / half + y / half).is_multiple_of(2) {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{DIRT, GRASS, MUD},
+        resource_pack::tests::{zip_pack, TEXTURES_MANIFEST},
+    };
+
+    fn solid(size: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(size, size, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn same_name_shares_a_layer() {
+        let mut packer = TexturePacker::new();
+        let dirt = packer.add("dirt", solid(16, 1));
+        let grass = packer.add("grass", solid(16, 2));
+        assert_ne!(dirt, grass);
+        assert_ne!(dirt, MISSING_LAYER);
+        assert_eq!(packer.add("dirt", solid(16, 3)), dirt);
+
+        let layers = packer.pack();
+        assert_eq!(layers.len(), 3);
+        assert_eq!(layers[dirt as usize].get_pixel(0, 0)[0], 3);
+    }
+
+    #[test]
+    fn layers_are_scaled_to_the_largest() {
+        let mut packer = TexturePacker::new();
+        let small = packer.add("small", solid(8, 10));
+        packer.add("large", solid(32, 20));
+
+        let layers = packer.pack();
+        assert!(layers.iter().all(|layer| layer.dimensions() == (32, 32)));
+        assert_eq!(layers[small as usize].get_pixel(31, 31)[0], 10);
+    }
+
+    #[test]
+    fn faces_fall_back_to_side_then_block() {
+        let mut packer = TexturePacker::new();
+        let top = packer.add("grass_top", solid(16, 1));
+        let side = packer.add("grass_side", solid(16, 2));
+        let grass = packer.add("grass", solid(16, 3));
+        let dirt = packer.add("dirt", solid(16, 4));
+
+        let textures = BlockTextures::from_packer(packer, &BlockRegistry::default());
+        assert_eq!(textures.faces(GRASS), [side, side, top, grass, side, side]);
+        assert_eq!(textures.faces(DIRT), [dirt; 6]);
+        assert_eq!(textures.faces(MUD), [MISSING_LAYER; 6]);
+    }
+
+    #[test]
+    fn later_packs_replace_textures() {
+        let png = |value| {
+            let mut bytes = std::io::Cursor::new(vec![]);
+            solid(16, value)
+                .write_to(&mut bytes, ImageFormat::Png)
+                .unwrap();
+            bytes.into_inner()
+        };
+        let (base_dirt, base_grass, dirt) = (png(1), png(2), png(3));
+        let base = zip_pack(&[
+            ("pack.ron", TEXTURES_MANIFEST),
+            ("blocks/dirt.png", &base_dirt),
+            ("blocks/grass.png", &base_grass),
+        ])
+        .unwrap();
+        let pack =
+            zip_pack(&[("pack.ron", TEXTURES_MANIFEST), ("blocks/dirt.png", &dirt)]).unwrap();
+
+        let packs = ResourcePacks::new(vec![base, pack]);
+        let textures = BlockTextures::load(&packs, &BlockRegistry::default()).unwrap();
+        let value = |block| textures.layers[textures.faces(block)[0] as usize].get_pixel(0, 0)[0];
+        assert_eq!(value(DIRT), 3);
+        assert_eq!(value(GRASS), 2);
+    }
+
+    #[test]
+    fn broken_texture_is_an_error() {
+        let pack = zip_pack(&[
+            ("pack.ron", TEXTURES_MANIFEST),
+            ("blocks/dirt.png", b"not a png"),
+        ])
+        .unwrap();
+        let packs = ResourcePacks::new(vec![pack]);
+        assert!(BlockTextures::load(&packs, &BlockRegistry::default()).is_err());
+    }
+}
@@ -1,22 +1,24 @@
+use crate::{
+    frustum::Frustum,
+    input::{Action, Input},
+};
 use cgmath::*;
-use winit::dpi::PhysicalPosition;
-use winit::event::*;
 // use std::time::Duration;
 use std::f32::consts::FRAC_PI_2;
 
+// Maps OpenGL's -1..1 depth range to the 0..1 wgpu expects. cgmath matrices
+// are column major, so the translation goes in the last column
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
-const CAM_SPEED: f32 = 50.;
-
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
@@ -36,6 +38,23 @@ impl Camera {
         }
     }
 
+    // Blends between two poses, used to render in between simulation ticks
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            position: self.position + (other.position - self.position) * t,
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+
+    // Forward and right, flattened onto the ground
+    pub fn horizontal_axes(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let (yaw_sin, yaw_cos) = self.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+        (forward, right)
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
@@ -70,7 +89,16 @@ impl Projection {
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        self.calc_matrix_between(self.znear, self.zfar)
+    }
+
+    // The same projection with other near and far planes, for pieces of the view
+    pub fn calc_matrix_between(&self, znear: f32, zfar: f32) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, znear, zfar)
+    }
+
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
     }
 }
 
@@ -87,10 +115,12 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    move_speed: f32,
+    invert_y: bool,
 }
 
 impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(speed: f32, sensitivity: f32, move_speed: f32, invert_y: bool) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -103,64 +133,49 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            move_speed,
+            invert_y,
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
-        let amount = if state == ElementState::Pressed {
-            CAM_SPEED
-        } else {
-            0.0
-        };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.amount_forward = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.amount_backward = amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.amount_left = amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.amount_right = amount;
-                true
+    pub fn process_input(&mut self, input: &Input) {
+        let amount = |action| {
+            if input.is_pressed(action) {
+                self.move_speed
+            } else {
+                0.0
             }
-            VirtualKeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
-        }
+        };
+        self.amount_forward = amount(Action::MoveForward);
+        self.amount_backward = amount(Action::MoveBackward);
+        self.amount_left = amount(Action::MoveLeft);
+        self.amount_right = amount(Action::MoveRight);
+        self.amount_up = amount(Action::MoveUp);
+        self.amount_down = amount(Action::MoveDown);
+
+        // I'm assuming a line is about 100 pixels
+        let lines = input.scroll(Action::ZoomIn) - input.scroll(Action::ZoomOut);
+        self.scroll += lines * 100.0 * self.move_speed;
     }
 
+    // Accumulates until the next tick, since several motion events can arrive per tick
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        self.rotate_horizontal += mouse_dx as f32;
+        let dy = if self.invert_y { -mouse_dy } else { mouse_dy };
+        self.rotate_vertical += dy as f32;
     }
 
-    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
-            // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => -scroll * 100.0 * CAM_SPEED,
-            MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => -*scroll as f32,
-        };
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: std::time::Duration) {
+        self.move_camera(camera, dt);
+        self.rotate_camera(camera);
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: std::time::Duration) {
+    // Free flying movement, the camera goes wherever the keys take it
+    pub fn move_camera(&mut self, camera: &mut Camera, dt: std::time::Duration) {
         let dt = dt.as_secs_f32();
 
         // Move forward/backward and left/right
-        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
-        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
-        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+        let (forward, right) = camera.horizontal_axes();
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
@@ -168,6 +183,7 @@ impl CameraController {
         // Note: this isn't an actual zoom. The camera's position
         // changes when zooming. I've added this to make it easier
         // to get closer to an object you want to focus on.
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
         let scrollward =
             Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
@@ -177,10 +193,22 @@ impl CameraController {
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+    }
+
+    // Horizontal direction the movement keys point in, relative to where the camera looks
+    pub fn walk_direction(&mut self, camera: &Camera) -> Vector3<f32> {
+        // Zooming makes no sense when the camera is attached to a player
+        self.scroll = 0.0;
+        let (forward, right) = camera.horizontal_axes();
+        forward * (self.amount_forward - self.amount_backward)
+            + right * (self.amount_right - self.amount_left)
+    }
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+    // The mouse moved by the same amount however long the tick was, so unlike
+    // the movement this isn't scaled by dt
+    pub fn rotate_camera(&mut self, camera: &mut Camera) {
+        camera.yaw += Rad::from(Deg(self.rotate_horizontal * self.sensitivity));
+        camera.pitch += Rad::from(Deg(-self.rotate_vertical * self.sensitivity));
 
         // If process_mouse isn't called every frame, these values
         // will not get set to zero, and the camera will rotate
@@ -220,6 +248,14 @@ impl CameraUniform {
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
     }
+
+    pub fn view_position(&self) -> [f32; 4] {
+        self.view_position
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.view_proj.into())
+    }
 }
 
 /*use cgmath::{InnerSpace, SquareMatrix};
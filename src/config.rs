@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    day_night::DayNightConfig, fog::FogConfig, input::InputMap, light::Sun, shadow::ShadowConfig,
+    sky::SkyConfig, water::WaterConfig, world::WORLD_SIZE,
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "voxel.ron";
+
+#[derive(Parser, Debug)]
+#[command(version, about = "A voxel engine")]
+pub struct Cli {
+    /// Config file to load, defaults to voxel.ron in the working directory
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Seed used for terrain generation
+    #[arg(long)]
+    pub seed: Option<u32>,
+    /// Directory the world is stored in
+    #[arg(long)]
+    pub world: Option<PathBuf>,
+    /// Window width in pixels
+    #[arg(long)]
+    pub width: Option<u32>,
+    /// Window height in pixels
+    #[arg(long)]
+    pub height: Option<u32>,
+    /// Start in borderless fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Enable or disable vsync
+    #[arg(long)]
+    pub vsync: Option<bool>,
+    /// Resource pack to load on top of the ones in the config, can be repeated
+    #[arg(long = "resource-pack")]
+    pub resource_packs: Vec<PathBuf>,
+    /// Render distance in blocks
+    #[arg(long)]
+    pub render_distance: Option<u32>,
+    /// Cull chunks in a compute shader and draw them indirectly
+    #[arg(long)]
+    pub gpu_culling: Option<bool>,
+    /// Samples per pixel for anti-aliasing: 1, 2, 4 or 8
+    #[arg(long)]
+    pub msaa: Option<u32>,
+    /// Resolution the world is drawn at compared to the window
+    #[arg(long)]
+    pub render_scale: Option<f32>,
+    /// Reload shaders and textures when their files change
+    #[arg(long)]
+    pub hot_reload: bool,
+    /// Simulation ticks per second
+    #[arg(long)]
+    pub tick_rate: Option<f64>,
+    /// Time of day to start at, 0.0 is midnight and 0.5 noon
+    #[arg(long)]
+    pub time: Option<f32>,
+    /// Keep the time of day from moving
+    #[arg(long)]
+    pub freeze_time: bool,
+    /// Record all input to this file
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Replay a recording instead of taking live input
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+    /// Run the replay without a window and print the final camera
+    #[arg(long, requires = "replay")]
+    pub headless: bool,
+    /// Print the resulting config and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Seed used for terrain generation. Default: 0
+    pub seed: u32,
+    /// Directory the world is stored in. Default: "world"
+    pub world_path: PathBuf,
+    /// Width and depth of the generated terrain in blocks. Default: 1414
+    pub world_size: usize,
+    /// Resource packs to load, each a directory or zip with a `pack.ron`
+    /// manifest. Packs later in the list override the textures and shaders of
+    /// earlier ones. Default: ["assets"]
+    pub resource_packs: Vec<PathBuf>,
+    /// Render distance in blocks. Default: 1000
+    pub render_distance: u32,
+    /// Distance in blocks after which chunks are drawn at half resolution, every
+    /// time the distance doubles the resolution halves again. Default: 128.0
+    pub lod_distance: f32,
+    /// Cull chunks on the GPU and draw them with indirect draws, falls back to
+    /// culling on the CPU if the adapter doesn't support it. Default: false
+    pub gpu_culling: bool,
+    /// Samples per pixel for multisample anti-aliasing, 1 turns it off. 2, 4
+    /// and 8 are used if the adapter supports them, otherwise the most it does
+    /// below that. Default: 4
+    pub msaa: u32,
+    /// Resolution the world is drawn at compared to the window, from 0.25 to
+    /// 2.0. Below 1.0 is faster but blurrier, above it is sharper but slower.
+    /// Default: 1.0
+    pub render_scale: f32,
+    /// Watch the resource packs and src/shader.wgsl, and reload shaders and
+    /// textures when they change. Meant for development. Default: false
+    pub hot_reload: bool,
+    /// Simulation ticks per second. Default: 60.0
+    pub tick_rate: f64,
+    /// Most ticks simulated in a single frame before falling behind. Default: 5
+    pub max_ticks_per_frame: u32,
+    pub window: WindowConfig,
+    pub camera: CameraConfig,
+    /// Sunlight at noon, the day/night cycle moves it around and dims it
+    pub sun: Sun,
+    pub day_night: DayNightConfig,
+    pub sky: SkyConfig,
+    pub fog: FogConfig,
+    pub water: WaterConfig,
+    pub shadows: ShadowConfig,
+    /// Bindings per action, e.g. `move_forward: [Key(W), Key(Up)]`. Actions that
+    /// are left out keep their default bindings
+    pub bindings: InputMap,
+
+    // Only set from the command line
+    #[serde(skip)]
+    pub record: Option<PathBuf>,
+    #[serde(skip)]
+    pub replay: Option<PathBuf>,
+    #[serde(skip)]
+    pub headless: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    /// Default: 1000
+    pub width: u32,
+    /// Default: 1000
+    pub height: u32,
+    /// Default: false
+    pub fullscreen: bool,
+    /// Default: true
+    pub vsync: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// Starting position. Default: (0.0, 400.0, 10.0)
+    pub position: (f32, f32, f32),
+    /// Starting yaw in degrees. Default: 0.0
+    pub yaw: f32,
+    /// Starting pitch in degrees. Default: -20.0
+    pub pitch: f32,
+    /// Vertical field of view in degrees. Default: 45.0
+    pub fovy: f32,
+    /// Default: 0.1
+    pub znear: f32,
+    /// Multiplier on the movement speed. Default: 4.0
+    pub speed: f32,
+    /// Degrees the camera turns per pixel the mouse moves. Default: 0.4
+    pub sensitivity: f32,
+    /// Moving the mouse up looks down. Default: false
+    pub invert_y: bool,
+    /// Start as a free flying camera instead of walking. Default: false
+    pub fly: bool,
+    /// Base movement speed in blocks per second. Default: 50.0
+    pub move_speed: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            world_path: PathBuf::from("world"),
+            world_size: WORLD_SIZE,
+            resource_packs: vec![PathBuf::from("assets")],
+            render_distance: 1000,
+            lod_distance: 128.0,
+            gpu_culling: false,
+            msaa: 4,
+            render_scale: 1.0,
+            hot_reload: false,
+            tick_rate: 60.0,
+            max_ticks_per_frame: 5,
+            window: WindowConfig::default(),
+            camera: CameraConfig::default(),
+            sun: Sun::default(),
+            day_night: DayNightConfig::default(),
+            sky: SkyConfig::default(),
+            fog: FogConfig::default(),
+            water: WaterConfig::default(),
+            shadows: ShadowConfig::default(),
+            bindings: InputMap::default(),
+            record: None,
+            replay: None,
+            headless: false,
+        }
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1000,
+            height: 1000,
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            position: (0.0, 400.0, 10.0),
+            yaw: 0.0,
+            pitch: -20.0,
+            fovy: 45.0,
+            znear: 0.1,
+            speed: 4.0,
+            sensitivity: 0.4,
+            invert_y: false,
+            fly: false,
+            move_speed: 50.0,
+        }
+    }
+}
+
+impl Config {
+    // Parses the command line, loads the config file and lets the flags override it
+    pub fn from_args() -> Result<Self> {
+        let cli = Cli::parse();
+
+        let mut config = match &cli.config {
+            Some(path) => Self::load(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::load(DEFAULT_CONFIG_PATH)?,
+            None => Self::default(),
+        };
+        config.apply_cli(&cli);
+        config.validate()?;
+
+        if cli.print_config {
+            println!("{}", config.to_ron()?);
+            std::process::exit(0);
+        }
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        ron::from_str(&text)
+            .with_context(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    // Catches settings that would otherwise panic later on
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.tick_rate.is_finite() && self.tick_rate > 0.0,
+            "tick_rate has to be a positive number, got {}",
+            self.tick_rate
+        );
+        ensure!(
+            self.lod_distance.is_finite() && self.lod_distance > 0.0,
+            "lod_distance has to be a positive number, got {}",
+            self.lod_distance
+        );
+        Ok(())
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(seed) = cli.seed {
+            self.seed = seed;
+        }
+        if let Some(world) = &cli.world {
+            self.world_path = world.clone();
+        }
+        if let Some(width) = cli.width {
+            self.window.width = width;
+        }
+        if let Some(height) = cli.height {
+            self.window.height = height;
+        }
+        if cli.fullscreen {
+            self.window.fullscreen = true;
+        }
+        if let Some(vsync) = cli.vsync {
+            self.window.vsync = vsync;
+        }
+        self.resource_packs
+            .extend(cli.resource_packs.iter().cloned());
+        if let Some(render_distance) = cli.render_distance {
+            self.render_distance = render_distance;
+        }
+        if let Some(gpu_culling) = cli.gpu_culling {
+            self.gpu_culling = gpu_culling;
+        }
+        if let Some(msaa) = cli.msaa {
+            self.msaa = msaa;
+        }
+        if let Some(render_scale) = cli.render_scale {
+            self.render_scale = render_scale;
+        }
+        if cli.hot_reload {
+            self.hot_reload = true;
+        }
+        if let Some(tick_rate) = cli.tick_rate {
+            self.tick_rate = tick_rate;
+        }
+        if let Some(time) = cli.time {
+            self.day_night.start_time = time;
+        }
+        if cli.freeze_time {
+            self.day_night.frozen = true;
+        }
+        self.record = cli.record.clone();
+        self.replay = cli.replay.clone();
+        self.headless = cli.headless;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_bad_tick_rates() {
+        for tick_rate in [0.0, -60.0, f64::NAN, f64::INFINITY] {
+            let config = Config {
+                tick_rate,
+                ..Config::default()
+            };
+            assert!(config.validate().is_err(), "{tick_rate} was accepted");
+        }
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_lod_distances() {
+        for lod_distance in [0.0, -128.0, f32::NAN, f32::INFINITY] {
+            let config = Config {
+                lod_distance,
+                ..Config::default()
+            };
+            assert!(config.validate().is_err(), "{lod_distance} was accepted");
+        }
+        assert!(Config::default().validate().is_ok());
+    }
+}
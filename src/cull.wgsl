@@ -0,0 +1,98 @@
+// Frustum culls every chunk and writes the indirect draw for the ones that are visible
+
+const LOD_LEVELS: u32 = 4u;
+// Same as gpu_cull::CULLED.len()
+const PASSES: u32 = 2u;
+
+struct Chunk {
+    min: vec4<f32>,
+    max: vec4<f32>,
+    // Per level of detail
+    first_instance: vec4<u32>,
+    instance_count: vec4<u32>,
+    occluded: u32,
+    // Which pass the chunk's instances are drawn in
+    pass_index: u32,
+};
+
+struct Cull {
+    planes: array<vec4<f32>, 6>,
+    view_position: vec4<f32>,
+    lod_distance: f32,
+    // Per pass, there's an entry in chunks for every one in each pass
+    chunk_count: u32,
+    index_count: u32,
+    // Pack the visible draws at the front instead of leaving empty draws for
+    // culled chunks, needs multi_draw_indexed_indirect_count
+    compact: u32,
+};
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> chunks: array<Chunk>;
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+@group(0) @binding(3)
+var<storage, read_write> draw_counts: array<atomic<u32>, PASSES>;
+
+// Same test as Frustum::intersects_aabb, after the occlusion culling done on the CPU
+fn is_visible(chunk: Chunk) -> bool {
+    if chunk.occluded != 0u {
+        return false;
+    }
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+        let corner = select(chunk.min.xyz, chunk.max.xyz, plane.xyz >= vec3<f32>(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Same as lod::level
+fn lod_level(chunk: Chunk) -> u32 {
+    let eye = cull.view_position.xyz;
+    let to_chunk = distance(eye, clamp(eye, chunk.min.xyz, chunk.max.xyz));
+    if to_chunk < cull.lod_distance {
+        return 0u;
+    }
+    return min(u32(log2(to_chunk / cull.lod_distance)) + 1u, LOD_LEVELS - 1u);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= cull.chunk_count * PASSES {
+        return;
+    }
+    let chunk = chunks[i];
+    // Each pass has its own run of draws
+    let first_draw = chunk.pass_index * cull.chunk_count;
+    let visible = is_visible(chunk);
+    let level = lod_level(chunk);
+    let first_instance = chunk.first_instance[level];
+    let instance_count = chunk.instance_count[level];
+
+    if cull.compact != 0u {
+        if !visible {
+            return;
+        }
+        let slot = first_draw + atomicAdd(&draw_counts[chunk.pass_index], 1u);
+        draws[slot] = DrawIndexedIndirect(cull.index_count, instance_count, 0u, 0, first_instance);
+    } else {
+        if visible {
+            atomicAdd(&draw_counts[chunk.pass_index], 1u);
+        }
+        draws[i] = DrawIndexedIndirect(cull.index_count, select(0u, instance_count, visible), 0u, 0, first_instance);
+    }
+}
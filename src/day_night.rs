@@ -0,0 +1,234 @@
+use std::{f32::consts::TAU, time::Duration};
+
+use cgmath::{InnerSpace, Vector3, VectorSpace};
+use serde::{Deserialize, Serialize};
+
+use crate::light::Sun;
+
+// Times of day are a fraction of the whole day starting at midnight, so noon is
+// 0.5 and sunset 0.75
+const SUNRISE: f32 = 0.25;
+// How far the time skips forward or back per key press
+pub const HOUR: f32 = 1.0 / 24.0;
+
+// Moonlight, much dimmer and bluer than the sun
+const MOON_COLOUR: (f32, f32, f32) = (0.12, 0.14, 0.22);
+// Ambient light at night, with the moon or without
+const NIGHT_AMBIENT: (f32, f32, f32) = (0.04, 0.05, 0.09);
+
+// Sky colours straight up and at the horizon
+const DAY_ZENITH: (f32, f32, f32) = (0.25, 0.45, 0.85);
+const DAY_HORIZON: (f32, f32, f32) = (0.65, 0.78, 0.95);
+const NIGHT_ZENITH: (f32, f32, f32) = (0.005, 0.008, 0.025);
+const NIGHT_HORIZON: (f32, f32, f32) = (0.02, 0.03, 0.06);
+// Horizon glow around sunrise and sunset
+const TWILIGHT_HORIZON: (f32, f32, f32) = (0.95, 0.45, 0.2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DayNightConfig {
+    /// Real seconds in a whole day and night. Default: 600.0
+    pub day_length: f32,
+    /// Time of day to start at, from 0.0 at midnight through 0.25 at sunrise,
+    /// 0.5 at noon and 0.75 at sunset. Default: 0.3
+    pub start_time: f32,
+    /// Keep the time of day from moving. Default: false
+    pub frozen: bool,
+}
+
+impl Default for DayNightConfig {
+    fn default() -> Self {
+        Self {
+            day_length: 600.0,
+            start_time: 0.3,
+            frozen: false,
+        }
+    }
+}
+
+// Time of day, advanced by the simulation so it replays the same way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldTime {
+    time_of_day: f32,
+    day_length: f32,
+    pub frozen: bool,
+}
+
+impl WorldTime {
+    pub fn new(config: &DayNightConfig) -> Self {
+        let mut time = Self {
+            time_of_day: 0.0,
+            day_length: config.day_length.max(f32::EPSILON),
+            frozen: config.frozen,
+        };
+        time.set_time_of_day(config.start_time);
+        time
+    }
+
+    pub fn time_of_day(&self) -> f32 {
+        self.time_of_day
+    }
+
+    // Wraps around into the day before or after
+    pub fn set_time_of_day(&mut self, time_of_day: f32) {
+        self.time_of_day = time_of_day.rem_euclid(1.0);
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        if !self.frozen {
+            self.set_time_of_day(self.time_of_day + dt.as_secs_f32() / self.day_length);
+        }
+    }
+}
+
+// Lighting and sky colours at a time of day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayNight {
+    // The sun by day and the moon by night
+    pub light: Sun,
+    // Towards the sun and moon, normalized. They're always opposite each other
+    pub sun_direction: Vector3<f32>,
+    pub moon_direction: Vector3<f32>,
+    // 1.0 through the day and 0.0 at night, blending across sunrise and sunset
+    pub daylight: f32,
+    pub zenith: Vector3<f32>,
+    pub horizon: Vector3<f32>,
+}
+
+impl DayNight {
+    // The sun goes round a circle that rises in the east, passes through the
+    // direction of `noon` at midday and sets in the west, with the moon across
+    // from it. Its colours are the ones at noon and fade with the sun's height
+    pub fn at(noon: &Sun, time_of_day: f32) -> Self {
+        let up = noon.direction();
+        // Perpendicular to noon and level with the horizon, so the sun rises
+        // and sets exactly a quarter of the day either side of noon
+        let east = Vector3::unit_y().cross(up);
+        let east = if east.magnitude2() > 0.0 {
+            east.normalize()
+        } else {
+            Vector3::unit_x()
+        };
+        let angle = (time_of_day - SUNRISE) * TAU;
+        let sun_direction = east * angle.cos() + up * angle.sin();
+        let moon_direction = -sun_direction;
+
+        let height = sun_direction.y;
+        let day = smoothstep(-0.1, 0.2, height);
+        let twilight = 1.0 - smoothstep(0.0, 0.3, height.abs());
+        // Whichever is up lights the world, both fade out at the horizon so
+        // there's no jump when switching from one to the other
+        let (direction, colour) = if height >= 0.0 {
+            let strength = smoothstep(0.0, 0.15, height);
+            (sun_direction, Vector3::from(noon.colour) * strength)
+        } else {
+            let strength = smoothstep(0.0, 0.15, -height);
+            (moon_direction, Vector3::from(MOON_COLOUR) * strength)
+        };
+        let ambient = Vector3::from(NIGHT_AMBIENT).lerp(Vector3::from(noon.ambient), day);
+
+        let zenith = Vector3::from(NIGHT_ZENITH).lerp(Vector3::from(DAY_ZENITH), day);
+        let horizon = Vector3::from(NIGHT_HORIZON)
+            .lerp(Vector3::from(DAY_HORIZON), day)
+            .lerp(Vector3::from(TWILIGHT_HORIZON), twilight * 0.6);
+
+        Self {
+            light: Sun {
+                direction: direction.into(),
+                colour: colour.into(),
+                ambient: ambient.into(),
+            },
+            sun_direction,
+            moon_direction,
+            daylight: day,
+            zenith,
+            horizon,
+        }
+    }
+}
+
+fn smoothstep(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn time_wraps_around_and_can_be_frozen() {
+        let mut time = WorldTime::new(&DayNightConfig {
+            day_length: 100.0,
+            start_time: 0.9,
+            frozen: false,
+        });
+        time.advance(Duration::from_secs(20));
+        assert!((time.time_of_day() - 0.1).abs() < 1e-4);
+
+        time.set_time_of_day(-HOUR);
+        assert!((time.time_of_day() - (1.0 - HOUR)).abs() < 1e-4);
+
+        time.frozen = true;
+        time.advance(Duration::from_secs(30));
+        assert!((time.time_of_day() - (1.0 - HOUR)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sun_passes_through_noon_direction() {
+        let sun = Sun::default();
+        let noon = DayNight::at(&sun, 0.5);
+        assert_close(noon.sun_direction, sun.direction());
+        assert_close(Vector3::from(noon.light.colour), Vector3::from(sun.colour));
+        assert_close(
+            Vector3::from(noon.light.ambient),
+            Vector3::from(sun.ambient),
+        );
+        assert_close(noon.moon_direction, -sun.direction());
+    }
+
+    #[test]
+    fn sun_rises_and_sets_at_the_horizon() {
+        let sun = Sun::default();
+        let sunrise = DayNight::at(&sun, SUNRISE);
+        let sunset = DayNight::at(&sun, 0.75);
+        assert!(sunrise.sun_direction.y.abs() < 1e-4);
+        assert!(sunset.sun_direction.y.abs() < 1e-4);
+        assert_close(sunrise.sun_direction, -sunset.sun_direction);
+        // No light at all right on the horizon, so switching to the moon
+        // doesn't jump
+        assert_close(
+            Vector3::from(sunrise.light.colour),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn moon_lights_the_night() {
+        let night = DayNight::at(&Sun::default(), 0.0);
+        assert!(night.sun_direction.y < 0.0);
+        assert_close(Vector3::from(night.light.direction), night.moon_direction);
+        assert_close(
+            Vector3::from(night.light.colour),
+            Vector3::from(MOON_COLOUR),
+        );
+        assert_close(
+            Vector3::from(night.light.ambient),
+            Vector3::from(NIGHT_AMBIENT),
+        );
+    }
+
+    #[test]
+    fn sun_straight_overhead_still_moves() {
+        let overhead = Sun {
+            direction: (0.0, 1.0, 0.0),
+            ..Sun::default()
+        };
+        let sunrise = DayNight::at(&overhead, SUNRISE);
+        assert_close(sunrise.sun_direction, Vector3::unit_x());
+    }
+}
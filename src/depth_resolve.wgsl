@@ -0,0 +1,18 @@
+// Copies the multisampled depth into a texture with one sample per pixel, so
+// the water can read it
+
+// One triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var depth: texture_depth_multisampled_2d;
+
+// The first sample is as good as any for telling how deep the water is
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    return textureLoad(depth, vec2<i32>(position.xy), 0);
+}
@@ -1,53 +1,193 @@
 use crate::{
+    block::Transparency,
+    block_textures::BlockTextures,
     camera::*,
+    config::Config,
+    day_night::DayNight,
+    fog::{FogConfig, FogUniform},
+    gpu_cull::{GpuCuller, CULLED},
+    hot_reload::HotReload,
+    input::{Action, InputEvent},
     instance::*,
+    light::{LightUniform, Sun},
+    render_target::{RenderTarget, RENDER_SCALE_STEP},
+    replay::{Recording, Replay},
+    resource_pack::ResourcePacks,
+    shadow::Shadows,
+    simulation::Simulation,
+    sky::{self, Sky},
     texture::Texture,
+    timestep::FixedTimestep,
     vertices::{self, INDICES},
-    Manager, WINDOW_SIZE,
+    visibility::VisibleSet,
+    water::Water,
+    world::chunk_pos,
+    Manager,
+};
+use anyhow::{bail, Context, Result};
+use cgmath::{EuclideanSpace, MetricSpace, Vector3};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
 };
 use wgpu::util::DeviceExt;
-use winit::{event::*, window::Window};
+use winit::{
+    event::*,
+    window::{CursorGrabMode, Window},
+};
+
+// The built in shader in the source tree, read instead of the copy compiled in
+// when hot reloading so it can be edited without rebuilding
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+// A resource pack's shader if there is one, then the one in the source tree when
+// hot reloading, otherwise the one compiled in
+fn shader_source(packs: &ResourcePacks, hot_reload: bool) -> Result<String> {
+    if let Some(source) = packs.shader("shader.wgsl")? {
+        return Ok(source);
+    }
+    if hot_reload && Path::new(SHADER_PATH).exists() {
+        return std::fs::read_to_string(SHADER_PATH)
+            .with_context(|| format!("failed to read {}", SHADER_PATH));
+    }
+    Ok(include_str!("shader.wgsl").to_string())
+}
+
+// One per kind of transparency
+fn create_render_pipelines(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    shader: &wgpu::ShaderModule,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    sample_count: u32,
+) -> [wgpu::RenderPipeline; 3] {
+    Transparency::ALL.map(|transparency| {
+        Manager::create_render_pipeline(
+            device,
+            config,
+            shader,
+            bind_group_layouts,
+            transparency,
+            sample_count,
+        )
+    })
+}
+
+fn translucent_chunks(chunks: &[ChunkInstances]) -> Vec<usize> {
+    (0..chunks.len())
+        .filter(|&i| chunks[i].has_translucent())
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChunkStats {
+    pub drawn: u32,
+    pub culled: u32,
+    // Hidden behind terrain
+    pub occluded: u32,
+}
 
 pub struct Engine {
     pub manager: Manager,
 
-    render_pipeline: wgpu::RenderPipeline,
+    // One per kind of transparency, in the order they're drawn
+    render_pipelines: [wgpu::RenderPipeline; 3],
+    // Kept to rebuild the pipelines when the number of samples changes
+    shader: wgpu::ShaderModule,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
     block_bind_group: wgpu::BindGroup,
-    camera: Camera,
     cam_uniform: CameraUniform,
     projection: Projection,
-    pub camera_controller: CameraController,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    instances: Vec<Instance>,
+    chunks: Vec<ChunkInstances>,
+    // Indices of the chunks with translucent instances, the only ones culled on
+    // the CPU when culling on the GPU since they still have to be sorted
+    translucent_chunks: Vec<usize>,
+    lod_distance: f32,
+    // Instance ranges of the chunks that passed culling this frame, per kind of
+    // transparency. Translucent ones are in the order they're drawn
+    visible: [Vec<Range<u32>>; 3],
+    translucent: TranslucentInstances,
+    chunk_stats: ChunkStats,
+    // Chunks that can be seen from the camera's chunk, None if it's outside the world
+    potentially_visible: Option<VisibleSet>,
+    // The camera's chunk and the world's generation it was worked out for
+    visibility_origin: Option<(Vector3<i32>, u64)>,
+    // Replaces the CPU culling above when enabled and supported
+    gpu_culler: Option<GpuCuller>,
     instance_buffer: wgpu::Buffer,
-    depth_texture: Texture,
-    pub mouse_pressed: bool,
-    count: f32,
-    count_buffer: wgpu::Buffer,
-    count_bind_group: wgpu::BindGroup,
+    render_target: RenderTarget,
+    pub sim: Simulation,
+    timestep: FixedTimestep,
+    cursor_grabbed: bool,
+    replay: Option<Replay>,
+    record_path: Option<std::path::PathBuf>,
+    // The sun at noon, the day/night cycle works out the sun or moon lighting
+    // the world now from it
+    noon_sun: Sun,
+    day_night: DayNight,
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
+    fog: FogConfig,
+    fog_buffer: wgpu::Buffer,
+    shadows: Shadows,
+    sky: Sky,
+    water: Water,
+    // Kept to rebuild the pipeline and textures when hot reloading
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    light_layout: wgpu::BindGroupLayout,
+    block_faces: Vec<[u32; 6]>,
+    resource_packs: Vec<PathBuf>,
+    hot_reload: Option<HotReload>,
 }
 impl Engine {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window) -> Self {
-        let (surface, device, queue, config, size) = Manager::set_wgpu_up(window).await;
+    pub async fn new(
+        window: &Window,
+        settings: &Config,
+        replay: Option<Recording>,
+    ) -> Result<Self> {
+        let optional_features = if settings.gpu_culling {
+            GpuCuller::FEATURES
+        } else {
+            wgpu::Features::empty()
+        };
+        let (surface, device, queue, config, size, sample_counts) =
+            Manager::set_wgpu_up(window, settings.window.vsync, optional_features).await;
 
-        let block_bytes = include_bytes!("b.png");
-        let block_texture = Texture::from_bytes(&device, &queue, block_bytes, "block png").unwrap();
+        let mut sim = Simulation::new(settings);
 
-        let (block_bind_group, texture_bind_group_layout) =
-            Texture::create_bind_groups(&device, &block_texture);
+        let packs = ResourcePacks::load(&settings.resource_packs)?;
+        let block_textures = BlockTextures::load(&packs, &sim.world.blocks)?;
+        let block_texture = Texture::from_layers(
+            &device,
+            &queue,
+            &block_textures.layers,
+            Some("block textures"),
+        )?;
 
-        let camera = Camera::new((0.0, 400.0, 10.0), cgmath::Deg(0.0), cgmath::Deg(-20.0));
-        let projection =
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = CameraController::new(4.0, 0.4);
+        let texture_bind_group_layout = Texture::bind_group_layout(&device);
+        let block_bind_group =
+            Texture::create_bind_group(&device, &texture_bind_group_layout, &block_texture);
+        if settings.record.is_some() {
+            sim.start_recording(settings);
+        }
+
+        let cam = &settings.camera;
+        let projection = Projection::new(
+            config.width,
+            config.height,
+            cgmath::Deg(cam.fovy),
+            cam.znear,
+            settings.render_distance as f32,
+        );
 
         let mut cam_uniform = CameraUniform::new();
-        cam_uniform.update_view_proj(&camera, &projection); // UPDATED!
+        cam_uniform.update_view_proj(&sim.camera, &projection); // UPDATED!
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -59,7 +199,8 @@ impl Engine {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // The fragment shader needs the camera position for fog
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -79,50 +220,149 @@ impl Engine {
             label: Some("camera_bind_group"),
         });
 
-        let count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[0.]),
+        let render_target = RenderTarget::new(
+            &device,
+            &config,
+            sample_counts,
+            settings.msaa,
+            settings.render_scale,
+        );
+        let water = Water::new(&device, &render_target, &settings.water, &projection);
+
+        let day_night = DayNight::at(&settings.sun, sim.world_time.time_of_day());
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform::from(&day_night.light)]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let count_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("count_layout"),
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[FogUniform::new(
+                &settings.fog,
+                settings.render_distance as f32,
+                day_night.horizon,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let count_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &count_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: count_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
+        let shadows = Shadows::new(&device, &settings.shadows);
+        let skybox = sky::load_skybox(&settings.sky, &packs)?;
+        let sky = Sky::new(
+            &device,
+            &queue,
+            config.format,
+            render_target.sample_count(),
+            &settings.sky,
+            skybox.as_ref(),
+        );
+
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform(0),
+                uniform(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                uniform(4),
+            ],
+            label: Some("light_layout"),
         });
 
-        let (instances, instance_buffer) = Instance::create_instances(&device);
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadows.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadows.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadows.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: fog_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_bind_group"),
+        });
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let (chunks, instance_buffer, translucent) =
+            Instance::create_instances(&device, &sim.world, &block_textures);
 
-        let render_pipeline = Manager::create_render_pipeline(
+        let shader = Manager::create_shader_module(
+            &device,
+            "shader.wgsl",
+            shader_source(&packs, settings.hot_reload)?,
+        )
+        .await?;
+        let render_pipelines = create_render_pipelines(
             &device,
             &config,
-            &texture_bind_group_layout,
-            &camera_bind_group_layout,
-            &count_layout,
+            &shader,
+            &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &water.layout,
+                &light_layout,
+            ],
+            render_target.sample_count(),
         );
 
         let (vertex_buffer, index_buffer) = vertices::generate_buffers(&device);
         let num_indices = INDICES.len() as u32;
 
+        let gpu_culler = if settings.gpu_culling {
+            let culler = GpuCuller::new(&device, &chunks, num_indices, settings.lod_distance);
+            match &culler {
+                Some(culler) => log::info!("culling chunks on the GPU ({:?})", culler.mode),
+                None => log::warn!("GPU culling isn't supported, culling on the CPU instead"),
+            }
+            culler
+        } else {
+            None
+        };
+
+        let hot_reload = if settings.hot_reload {
+            let mut paths = settings.resource_packs.clone();
+            paths.extend(Path::new(SHADER_PATH).parent().map(Path::to_path_buf));
+            Some(HotReload::new(&paths)?)
+        } else {
+            None
+        };
+
         let manager = Manager {
             surface,
             device,
@@ -131,27 +371,50 @@ impl Engine {
             size,
         };
 
-        Self {
+        Ok(Self {
             manager,
             cam_uniform,
-            render_pipeline,
+            render_pipelines,
+            shader,
             vertex_buffer,
             index_buffer,
             num_indices,
             block_bind_group,
-            camera,
-            camera_controller,
             camera_buffer,
             projection,
             camera_bind_group,
-            instances,
+            translucent_chunks: translucent_chunks(&chunks),
+            chunks,
+            lod_distance: settings.lod_distance,
+            visible: Default::default(),
+            translucent,
+            chunk_stats: ChunkStats::default(),
+            potentially_visible: None,
+            visibility_origin: None,
+            gpu_culler,
             instance_buffer,
-            depth_texture,
-            mouse_pressed: false,
-            count: 0.,
-            count_buffer,
-            count_bind_group,
-        }
+            render_target,
+            sim,
+            timestep: FixedTimestep::new(settings.tick_rate, settings.max_ticks_per_frame),
+            cursor_grabbed: false,
+            replay: replay.map(Replay::new),
+            record_path: settings.record.clone(),
+            noon_sun: settings.sun,
+            day_night,
+            light_buffer,
+            light_bind_group,
+            fog: settings.fog.clone(),
+            fog_buffer,
+            shadows,
+            sky,
+            water,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
+            light_layout,
+            block_faces: block_textures.all_faces().to_vec(),
+            resource_packs: settings.resource_packs.clone(),
+            hot_reload,
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -164,57 +427,366 @@ impl Engine {
         self.manager
             .surface
             .configure(&self.manager.device, &self.manager.config);
-        self.depth_texture = Texture::create_depth_texture(
-            &self.manager.device,
-            &self.manager.config,
-            "depth_texture",
-        );
+        self.render_target
+            .resize(&self.manager.device, &self.manager.config);
+        self.water.resize(&self.manager.device, &self.render_target);
         self.projection.resize(new_size.width, new_size.height);
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        // self.camera_controller.process_events(event)
-        match event {
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        virtual_keycode: Some(key),
-                        state,
-                        ..
-                    },
-                ..
-            } => self.camera_controller.process_keyboard(*key, *state),
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.camera_controller.process_scroll(delta);
-                true
-            }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
-                true
-            }
-            _ => false,
+        InputEvent::from_window_event(event).is_some_and(|event| self.process_event(event))
+    }
+
+    pub fn process_event(&mut self, event: InputEvent) -> bool {
+        // Live input would make the replay diverge
+        if self.replay.is_some() {
+            return false;
         }
+        self.sim.process_event(event)
     }
 
-    pub fn update(&mut self, dt: std::time::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.cam_uniform
-            .update_view_proj(&self.camera, &self.projection);
+    // Grabs and hides the cursor while in mouse look mode, releases it otherwise
+    pub fn update_cursor(&mut self, window: &Window) {
+        if self.cursor_grabbed == self.sim.mouse_look {
+            return;
+        }
+        self.cursor_grabbed = self.sim.mouse_look;
+
+        let result = if self.cursor_grabbed {
+            // Not every platform can lock the cursor in place, fall back to confining it
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            log::warn!("failed to change cursor grab: {}", e);
+        }
+        window.set_cursor_visible(!self.cursor_grabbed);
+    }
+
+    pub fn save_recording(&mut self) {
+        let (Some(path), Some(recording)) = (&self.record_path, self.sim.take_recording()) else {
+            return;
+        };
+        match recording.save(path) {
+            Ok(()) => log::info!("saved recording to {}", path.display()),
+            Err(e) => log::error!("{:?}", e),
+        }
+    }
+
+    // Runs as many fixed ticks as the frame time allows, then uploads the
+    // camera interpolated between the last two ticks
+    pub fn update(&mut self, frame_dt: std::time::Duration) {
+        self.hot_reload();
+        for _ in 0..self.timestep.advance(frame_dt) {
+            self.tick();
+            if self.sim.exit_requested {
+                break;
+            }
+        }
+
+        let camera = self.sim.interpolated_camera(self.timestep.alpha());
+        self.cam_uniform.update_view_proj(&camera, &self.projection);
+        self.day_night = DayNight::at(&self.noon_sun, self.sim.world_time.time_of_day());
+        self.manager.queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[LightUniform::from(&self.day_night.light)]),
+        );
+        let fog = FogUniform::new(
+            &self.fog,
+            self.projection.depth_range().1,
+            self.day_night.horizon,
+        );
+        self.manager
+            .queue
+            .write_buffer(&self.fog_buffer, 0, bytemuck::cast_slice(&[fog]));
+        self.sky.update(
+            &self.manager.queue,
+            &camera,
+            &self.projection,
+            &self.day_night,
+            self.sim.time,
+        );
+        self.water
+            .update(&self.manager.queue, self.sim.time, &self.projection);
+        self.update_visibility(camera.position);
+        self.shadows.update(
+            &self.manager.queue,
+            &camera,
+            &self.projection,
+            self.day_night.light.direction(),
+            &self.chunks,
+            self.lod_distance,
+        );
+        self.cull_chunks(camera.position);
 
         self.manager.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.cam_uniform]),
         );
+    }
 
-        // self.count += 10.;
-        self.manager
-            .queue
-            .write_buffer(&self.count_buffer, 0, bytemuck::cast_slice(&[self.count]));
+    // Reloads the shader and textures that changed on disk. If anything fails to
+    // load the error is logged and the old version stays in use
+    fn hot_reload(&mut self) {
+        let Some(hot_reload) = &mut self.hot_reload else {
+            return;
+        };
+        let changes = hot_reload.changes();
+        if !changes.any() {
+            return;
+        }
+        let packs = match ResourcePacks::load(&self.resource_packs) {
+            Ok(packs) => packs,
+            Err(e) => return log::error!("{:?}", e),
+        };
+        if changes.textures {
+            match self.reload_textures(&packs) {
+                Ok(()) => log::info!("reloaded textures"),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+        if changes.shaders {
+            match self.reload_pipeline(&packs) {
+                Ok(()) => log::info!("reloaded shader"),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+    }
+
+    fn reload_textures(&mut self, packs: &ResourcePacks) -> Result<()> {
+        let device = &self.manager.device;
+        let block_textures = BlockTextures::load(packs, &self.sim.world.blocks)?;
+        let block_texture = Texture::from_layers(
+            device,
+            &self.manager.queue,
+            &block_textures.layers,
+            Some("block textures"),
+        )?;
+        let skybox = sky::load_skybox(self.sky.config(), packs)?;
+        self.sky
+            .set_skybox(device, &self.manager.queue, skybox.as_ref());
+        self.block_bind_group =
+            Texture::create_bind_group(device, &self.texture_bind_group_layout, &block_texture);
+
+        // Adding or removing a texture can move faces to other layers, which
+        // are stored in the instances
+        if block_textures.all_faces() != self.block_faces {
+            let (chunks, instance_buffer, translucent) =
+                Instance::create_instances(device, &self.sim.world, &block_textures);
+            self.translucent_chunks = translucent_chunks(&chunks);
+            self.chunks = chunks;
+            self.instance_buffer = instance_buffer;
+            self.translucent = translucent;
+            self.block_faces = block_textures.all_faces().to_vec();
+
+            // The culler has the old instance ranges, and the potentially
+            // visible set has to be uploaded to the new one
+            if self.gpu_culler.is_some() {
+                self.gpu_culler =
+                    GpuCuller::new(device, &self.chunks, self.num_indices, self.lod_distance);
+                self.visibility_origin = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn reload_pipeline(&mut self, packs: &ResourcePacks) -> Result<()> {
+        let device = &self.manager.device;
+        let shader = pollster::block_on(Manager::create_shader_module(
+            device,
+            "shader.wgsl",
+            shader_source(packs, true)?,
+        ))?;
+
+        // The shader can compile and still not fit the pipeline
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let render_pipelines = self.create_render_pipelines(&shader);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            bail!("failed to create the render pipeline: {}", error);
+        }
+        self.render_pipelines = render_pipelines;
+        self.shader = shader;
+        Ok(())
+    }
+
+    fn create_render_pipelines(&self, shader: &wgpu::ShaderModule) -> [wgpu::RenderPipeline; 3] {
+        create_render_pipelines(
+            &self.manager.device,
+            &self.manager.config,
+            shader,
+            &[
+                &self.texture_bind_group_layout,
+                &self.camera_bind_group_layout,
+                &self.water.layout,
+                &self.light_layout,
+            ],
+            self.render_target.sample_count(),
+        )
+    }
+
+    // Samples per pixel for MSAA, 1 turns it off. Falls back to fewer samples
+    // if the adapter doesn't support that many
+    pub fn set_msaa(&mut self, samples: u32) {
+        let device = &self.manager.device;
+        self.render_target
+            .set_sample_count(device, &self.manager.config, samples);
+        let sample_count = self.render_target.sample_count();
+        self.sky.set_sample_count(device, sample_count);
+        self.water.resize(device, &self.render_target);
+        self.render_pipelines = self.create_render_pipelines(&self.shader);
+        log::info!("{}x MSAA", sample_count);
+    }
+
+    // Size the world is drawn at compared to the window
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let device = &self.manager.device;
+        self.render_target
+            .set_scale(device, &self.manager.config, scale);
+        self.water.resize(device, &self.render_target);
+        log::info!("render scale {}", self.render_target.scale());
+    }
+
+    // Graphics settings aren't part of the simulation, but are bound to keys
+    // like everything else
+    fn apply_graphics_actions(&mut self) {
+        let input = &self.sim.input;
+        let (cycle_msaa, scale_up, scale_down) = (
+            input.just_pressed(Action::CycleMsaa),
+            input.just_pressed(Action::RenderScaleUp),
+            input.just_pressed(Action::RenderScaleDown),
+        );
+        if cycle_msaa {
+            self.set_msaa(self.render_target.next_sample_count());
+        }
+        if scale_up {
+            self.set_render_scale(self.render_target.scale() + RENDER_SCALE_STEP);
+        }
+        if scale_down {
+            self.set_render_scale(self.render_target.scale() - RENDER_SCALE_STEP);
+        }
+    }
+
+    // The sun at noon, see `DayNight::at` for how it moves through the day
+    pub fn sun(&self) -> Sun {
+        self.noon_sun
+    }
+
+    // Takes effect on the next frame
+    pub fn set_sun(&mut self, sun: Sun) {
+        self.noon_sun = sun;
+    }
+
+    // Not known when culling on the GPU, only the chunks with translucent
+    // instances are culled on the CPU then
+    pub fn chunk_stats(&self) -> Option<ChunkStats> {
+        self.gpu_culler.is_none().then_some(self.chunk_stats)
+    }
+
+    // The potentially visible set only depends on which chunk the camera is in,
+    // until a block is placed
+    fn update_visibility(&mut self, eye: cgmath::Point3<f32>) {
+        let origin = chunk_pos(eye.map(|c| c.floor() as i32).to_vec());
+        let generation = self.sim.world.generation();
+        if self.visibility_origin == Some((origin, generation)) {
+            return;
+        }
+        self.visibility_origin = Some((origin, generation));
+        self.potentially_visible = self.sim.world.visibility.potentially_visible(origin);
+
+        if let Some(culler) = &self.gpu_culler {
+            culler.set_potentially_visible(
+                &self.manager.queue,
+                &self.chunks,
+                self.potentially_visible.as_ref(),
+            );
+        }
+    }
+
+    // Collects the instances of every chunk inside the view frustum at the level
+    // of detail for its distance, merging ranges that follow each other so they
+    // can be drawn in one call. Translucent chunks are culled here even when the
+    // rest is culled on the GPU, since they have to be drawn farthest first
+    fn cull_chunks(&mut self, eye: cgmath::Point3<f32>) {
+        let frustum = self.cam_uniform.frustum();
+        for visible in &mut self.visible {
+            visible.clear();
+        }
+        self.chunk_stats = ChunkStats::default();
+        let mut translucent = vec![];
+
+        let all = &self.chunks;
+        let chunks: Box<dyn Iterator<Item = &ChunkInstances>> = match self.gpu_culler {
+            Some(_) => Box::new(self.translucent_chunks.iter().map(|&i| &all[i])),
+            None => Box::new(all.iter()),
+        };
+        for chunk in chunks {
+            if let Some(visible) = &self.potentially_visible {
+                if !visible.contains(chunk.pos) {
+                    self.chunk_stats.occluded += 1;
+                    continue;
+                }
+            }
+            if !frustum.intersects_aabb(&chunk.aabb) {
+                self.chunk_stats.culled += 1;
+                continue;
+            }
+            self.chunk_stats.drawn += 1;
+
+            for transparency in Transparency::ALL {
+                let instances = chunk.lod_instances(eye, self.lod_distance, transparency);
+                if instances.is_empty() {
+                    continue;
+                }
+                if transparency == Transparency::Translucent {
+                    let centre = chunk.aabb.min.midpoint(chunk.aabb.max);
+                    translucent.push((eye.distance2(centre), instances));
+                    continue;
+                }
+                if self.gpu_culler.is_some() {
+                    continue;
+                }
+                let visible = &mut self.visible[transparency as usize];
+                match visible.last_mut() {
+                    Some(last) if last.end == instances.start => last.end = instances.end,
+                    _ => visible.push(instances),
+                }
+            }
+        }
+
+        // Each chunk's instances are sorted in the instance buffer as well
+        translucent.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        for (_, instances) in translucent {
+            if let Some(sorted) = self.translucent.sort(instances.clone(), eye) {
+                self.manager.queue.write_buffer(
+                    &self.instance_buffer,
+                    instances.start as u64 * std::mem::size_of::<InstanceRaw>() as u64,
+                    bytemuck::cast_slice(sorted),
+                );
+            }
+            self.visible[Transparency::Translucent as usize].push(instances);
+        }
+    }
+
+    fn tick(&mut self) {
+        let mut dt = self.timestep.dt();
+        if let Some(replay) = &mut self.replay {
+            match replay.next_frame(&mut self.sim) {
+                Some(recorded_dt) => dt = recorded_dt,
+                None => {
+                    log::info!("replay finished: {:?}", self.sim.camera);
+                    self.replay = None;
+                    self.sim.exit_requested = true;
+                    return;
+                }
+            }
+        }
+        self.apply_graphics_actions();
+        self.sim.update(dt);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -230,52 +802,110 @@ impl Engine {
                     label: Some("Render Encoder"),
                 });
 
+        if let Some(culler) = &self.gpu_culler {
+            culler.cull(&self.manager.queue, &mut encoder, &self.cam_uniform);
+        }
+        self.shadows.render(
+            &mut encoder,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &self.instance_buffer,
+            self.num_indices,
+        );
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.2,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    }),
+                    // This is what @location(0) in the fragment shader targets.
+                    // The samples are resolved after the translucent pass
+                    Some(self.render_target.colour_attachment(
+                        &view,
+                        wgpu::LoadOp::Clear(wgpu::Color {
+                            r: self.day_night.horizon.x as f64,
+                            g: self.day_night.horizon.y as f64,
+                            b: self.day_night.horizon.z as f64,
+                            a: 1.0,
+                        }),
+                        false,
+                    )),
                 ],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: &self.render_target.depth.view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Discard,
+                        store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
                 }),
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            render_pass.set_scissor_rect(0, 0, WINDOW_SIZE.width, WINDOW_SIZE.height);
-
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            self.sky.render(&mut render_pass);
+            self.draw_blocks(
+                &mut render_pass,
+                &[Transparency::Opaque, Transparency::Cutout],
+            );
+        }
 
-            render_pass.set_bind_group(0, &self.block_bind_group, &[]);
-            render_pass.set_bind_group(2, &self.count_bind_group, &[]);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+        // Translucent blocks go in a pass of their own, after the water has a
+        // copy of the depth of everything behind them
+        self.water
+            .copy_scene_depth(&mut encoder, &self.render_target);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Translucent Pass"),
+                color_attachments: &[Some(self.render_target.colour_attachment(
+                    &view,
+                    wgpu::LoadOp::Load,
+                    true,
+                ))],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.render_target.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.draw_blocks(&mut render_pass, &[Transparency::Translucent]);
         }
+        self.render_target.upscale(&mut encoder, &view);
         self.manager.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
         Ok(())
     }
+
+    // Draws the chunks that passed culling for each kind of transparency, in order
+    fn draw_blocks<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        transparencies: &[Transparency],
+    ) {
+        let (width, height) = self.render_target.size();
+        render_pass.set_scissor_rect(0, 0, width, height);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(0, &self.block_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.water.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.light_bind_group, &[]);
+        for &transparency in transparencies {
+            render_pass.set_pipeline(&self.render_pipelines[transparency as usize]);
+            let gpu_pass = CULLED.iter().position(|culled| *culled == transparency);
+            match (&self.gpu_culler, gpu_pass) {
+                (Some(culler), Some(pass)) => culler.draw(render_pass, pass),
+                _ => {
+                    for instances in &self.visible[transparency as usize] {
+                        render_pass.draw_indexed(0..self.num_indices, 0, instances.clone());
+                    }
+                }
+            }
+        }
+    }
 }
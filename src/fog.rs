@@ -0,0 +1,81 @@
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FogMode {
+    Off,
+    // Thickens evenly from the start to the render distance
+    Linear,
+    // Creeps in slowly past the start, then closes in quickly before the
+    // render distance
+    Exponential,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FogConfig {
+    /// Off, Linear or Exponential. Default: Exponential
+    pub mode: FogMode,
+    /// Where the fog starts as a fraction of the render distance, it always
+    /// hides everything at the render distance. Default: 0.5
+    pub start: f32,
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Exponential,
+            start: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    // A vec3 in WGSL, start fills the rest of its 16 bytes
+    colour: [f32; 3],
+    // Distances from the camera in blocks
+    start: f32,
+    end: f32,
+    // 0 for off, 1 for linear and 2 for exponential
+    mode: u32,
+    _padding: [u32; 2],
+}
+
+impl FogUniform {
+    // Fog in the colour of the sky at the horizon, so terrain fades into it
+    pub fn new(config: &FogConfig, render_distance: f32, colour: Vector3<f32>) -> Self {
+        let mode = match config.mode {
+            FogMode::Off => 0,
+            FogMode::Linear => 1,
+            FogMode::Exponential => 2,
+        };
+        Self {
+            colour: colour.into(),
+            start: render_distance * config.start.clamp(0.0, 0.99),
+            end: render_distance,
+            mode,
+            _padding: [0; 2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::*;
+
+    // Has to match the Fog struct in shader.wgsl, where colour is a vec3 and
+    // start fills the last four bytes of its 16
+    #[test]
+    fn uniform_matches_the_shader_layout() {
+        assert_eq!(offset_of!(FogUniform, colour), 0);
+        assert_eq!(offset_of!(FogUniform, start), 12);
+        assert_eq!(offset_of!(FogUniform, end), 16);
+        assert_eq!(offset_of!(FogUniform, mode), 20);
+        // Structs in uniforms are rounded up to the alignment of a vec3
+        assert_eq!(size_of::<FogUniform>(), 32);
+    }
+}
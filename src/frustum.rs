@@ -0,0 +1,110 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+use crate::physics::Aabb;
+
+// The six planes of a view frustum with their normals pointing inwards, stored
+// as (normal, distance) so a point p is inside a plane if dot(normal, p) + distance >= 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a view projection matrix (Gribb & Hartmann). The
+    // matrix is expected to map depth to 0..1 like wgpu does.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let row = |i| view_proj.row(i);
+        let planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(2),          // near
+            row(3) - row(2), // far
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    // Conservative test, boxes near the corners of the frustum can be reported as
+    // visible even though they aren't
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, Point3};
+
+    use super::*;
+
+    // Camera at the origin looking down -z, seeing 1..100 units ahead
+    fn frustum() -> Frustum {
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vector3::unit_y(),
+        );
+        let proj = crate::camera::OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, 1.0, 100.0);
+        Frustum::from_matrix(proj * view)
+    }
+
+    fn cube(center: Point3<f32>) -> Aabb {
+        let half = Vector3::new(0.5, 0.5, 0.5);
+        Aabb::new(center - half, center + half)
+    }
+
+    #[test]
+    fn box_in_front_is_visible() {
+        assert!(frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, -10.0))));
+    }
+
+    #[test]
+    fn box_behind_is_culled() {
+        assert!(!frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, 10.0))));
+    }
+
+    #[test]
+    fn box_beside_is_culled() {
+        assert!(!frustum().intersects_aabb(&cube(Point3::new(20.0, 0.0, -10.0))));
+        assert!(!frustum().intersects_aabb(&cube(Point3::new(0.0, -20.0, -10.0))));
+    }
+
+    #[test]
+    fn box_past_far_plane_is_culled() {
+        assert!(!frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, -150.0))));
+    }
+
+    #[test]
+    fn box_crossing_a_plane_is_visible() {
+        // Straddles the right plane, which at this depth is at x = 10
+        assert!(frustum().intersects_aabb(&cube(Point3::new(10.2, 0.0, -10.0))));
+        // Straddles the near plane
+        assert!(frustum().intersects_aabb(&cube(Point3::new(0.0, 0.0, -1.2))));
+    }
+}
@@ -0,0 +1,287 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    block::Transparency, camera::CameraUniform, instance::ChunkInstances, lod::LOD_LEVELS,
+    visibility::VisibleSet,
+};
+
+// Size of wgpu's DrawIndexedIndirect arguments: five 32 bit values
+const DRAW_SIZE: u64 = 5 * 4;
+const WORKGROUP_SIZE: u32 = 64;
+// Passes that are culled here, in the order their draws are written. Translucent
+// chunks have to be sorted, so they're culled on the CPU
+pub const CULLED: [Transparency; 2] = [Transparency::Opaque, Transparency::Cutout];
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkRaw {
+    min: [f32; 4],
+    max: [f32; 4],
+    // Per level of detail
+    first_instance: [u32; LOD_LEVELS],
+    instance_count: [u32; LOD_LEVELS],
+    // Hidden behind terrain, see VisibilityGraph
+    occluded: u32,
+    // Index into CULLED
+    pass_index: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    view_position: [f32; 4],
+    lod_distance: f32,
+    chunk_count: u32,
+    index_count: u32,
+    compact: u32,
+}
+
+// How the culled draws get submitted, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawMode {
+    // Visible draws are packed together and the GPU reads how many there are
+    MultiDrawCount,
+    // One draw per chunk in a single call, culled chunks have no instances
+    MultiDraw,
+    // Same as MultiDraw, but a separate call per chunk
+    Single,
+}
+
+// Frustum culls chunks in a compute shader and draws them with indirect draws,
+// so the CPU never has to touch the chunk list
+pub struct GpuCuller {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    chunk_buffer: wgpu::Buffer,
+    cull_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    count_buffer: wgpu::Buffer,
+    // Per pass
+    chunk_count: u32,
+    index_count: u32,
+    lod_distance: f32,
+    pub mode: DrawMode,
+}
+
+impl GpuCuller {
+    // Features that are requested when GPU culling is enabled, only the first
+    // one is required
+    pub const FEATURES: wgpu::Features = wgpu::Features::INDIRECT_FIRST_INSTANCE
+        .union(wgpu::Features::MULTI_DRAW_INDIRECT)
+        .union(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT);
+
+    // Returns None if the device can't draw a chunk's instances indirectly
+    pub fn new(
+        device: &wgpu::Device,
+        chunks: &[ChunkInstances],
+        index_count: u32,
+        lod_distance: f32,
+    ) -> Option<Self> {
+        let features = device.features();
+        if !features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE) || chunks.is_empty() {
+            return None;
+        }
+        let mode = if features.contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT) {
+            DrawMode::MultiDrawCount
+        } else if features.contains(wgpu::Features::MULTI_DRAW_INDIRECT) {
+            DrawMode::MultiDraw
+        } else {
+            DrawMode::Single
+        };
+
+        let chunk_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Chunk Buffer"),
+            contents: bytemuck::cast_slice(&chunk_data(chunks, None)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cull_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            size: (CULLED.len() * chunks.len()) as u64 * DRAW_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Count Buffer"),
+            size: CULLED.len() as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+            ],
+            label: Some("cull_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: cull_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: chunk_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: draw_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: count_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("cull_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("cull.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Some(Self {
+            pipeline,
+            bind_group,
+            chunk_buffer,
+            cull_buffer,
+            draw_buffer,
+            count_buffer,
+            chunk_count: chunks.len() as u32,
+            index_count,
+            lod_distance,
+            mode,
+        })
+    }
+
+    // Chunks that aren't potentially visible are skipped by the culling pass
+    pub fn set_potentially_visible(
+        &self,
+        queue: &wgpu::Queue,
+        chunks: &[ChunkInstances],
+        visible: Option<&VisibleSet>,
+    ) {
+        queue.write_buffer(
+            &self.chunk_buffer,
+            0,
+            bytemuck::cast_slice(&chunk_data(chunks, visible)),
+        );
+    }
+
+    // Records the culling pass, has to run before the render pass that draws
+    pub fn cull(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &CameraUniform,
+    ) {
+        let uniform = CullUniform {
+            planes: camera.frustum().planes(),
+            view_position: camera.view_position(),
+            lod_distance: self.lod_distance,
+            chunk_count: self.chunk_count,
+            index_count: self.index_count,
+            compact: (self.mode == DrawMode::MultiDrawCount) as u32,
+        };
+        queue.write_buffer(&self.cull_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        encoder.clear_buffer(&self.count_buffer, 0, None);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        let entries = CULLED.len() as u32 * self.chunk_count;
+        pass.dispatch_workgroups(entries.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Draws the chunks of one of the CULLED passes
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, pass: usize) {
+        let first_draw = pass as u64 * self.chunk_count as u64 * DRAW_SIZE;
+        match self.mode {
+            DrawMode::MultiDrawCount => render_pass.multi_draw_indexed_indirect_count(
+                &self.draw_buffer,
+                first_draw,
+                &self.count_buffer,
+                pass as u64 * 4,
+                self.chunk_count,
+            ),
+            DrawMode::MultiDraw => render_pass.multi_draw_indexed_indirect(
+                &self.draw_buffer,
+                first_draw,
+                self.chunk_count,
+            ),
+            DrawMode::Single => {
+                for i in 0..self.chunk_count as u64 {
+                    render_pass
+                        .draw_indexed_indirect(&self.draw_buffer, first_draw + i * DRAW_SIZE);
+                }
+            }
+        }
+    }
+}
+
+// Every chunk once for each pass, all of the first pass before the next
+fn chunk_data(chunks: &[ChunkInstances], visible: Option<&VisibleSet>) -> Vec<ChunkRaw> {
+    CULLED
+        .iter()
+        .enumerate()
+        .flat_map(|(pass, transparency)| {
+            chunks.iter().map(move |chunk| {
+                let lods = &chunk.lods[*transparency as usize];
+                ChunkRaw {
+                    min: chunk.aabb.min.to_homogeneous().into(),
+                    max: chunk.aabb.max.to_homogeneous().into(),
+                    first_instance: lods.clone().map(|lod| lod.start),
+                    instance_count: lods.clone().map(|lod| lod.len() as u32),
+                    occluded: visible.is_some_and(|visible| !visible.contains(chunk.pos)) as u32,
+                    pass_index: pass as u32,
+                    _padding: [0; 2],
+                }
+            })
+        })
+        .collect()
+}
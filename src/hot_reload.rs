@@ -0,0 +1,98 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+
+// Editors tend to write a file in several steps, wait for them to finish
+// before reloading
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// What needs reloading after files changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes {
+    pub shaders: bool,
+    pub textures: bool,
+}
+
+impl Changes {
+    fn of_file(path: &Path) -> Self {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        match extension {
+            Some("wgsl") => Self {
+                shaders: true,
+                textures: false,
+            },
+            Some("png" | "jpg" | "jpeg") => Self {
+                shaders: false,
+                textures: true,
+            },
+            // A manifest or a whole zipped pack can change either
+            Some("ron" | "zip") => Self {
+                shaders: true,
+                textures: true,
+            },
+            _ => Self::default(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.shaders |= other.shaders;
+        self.textures |= other.textures;
+    }
+
+    pub fn any(self) -> bool {
+        self.shaders || self.textures
+    }
+}
+
+// Watches shader and texture files for changes during development
+pub struct HotReload {
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    pending: Changes,
+    last_event: Instant,
+}
+
+impl HotReload {
+    // Directories are watched recursively, paths that don't exist are skipped
+    pub fn new(paths: &[PathBuf]) -> Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for path in paths.iter().filter(|path| path.exists()) {
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .with_context(|| format!("failed to watch {}", path.display()))?;
+            log::info!("watching {} for changes", path.display());
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            pending: Changes::default(),
+            last_event: Instant::now(),
+        })
+    }
+
+    // Changes since the last call, once the files have stopped changing
+    pub fn changes(&mut self) -> Changes {
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if !event.kind.is_access() => {
+                    for path in &event.paths {
+                        self.pending.merge(Changes::of_file(path));
+                    }
+                    self.last_event = Instant::now();
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("file watcher error: {}", e),
+            }
+        }
+        if self.last_event.elapsed() < SETTLE_TIME {
+            return Changes::default();
+        }
+        std::mem::take(&mut self.pending)
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use winit::dpi::PhysicalPosition;
+use winit::event::*;
+
+// Pixel deltas (touchpads) are converted to lines, assuming a line is about 100 pixels
+const PIXELS_PER_LINE: f32 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Jump,
+    Sprint,
+    Crouch,
+    ZoomIn,
+    ZoomOut,
+    Look,
+    ToggleMouseLook,
+    BreakBlock,
+    PlaceBlock,
+    ToggleFly,
+    // Stops or restarts the day/night cycle
+    FreezeTime,
+    // Skip the time of day forward or back an hour
+    TimeForward,
+    TimeBackward,
+    // Step through the MSAA sample counts the adapter supports
+    CycleMsaa,
+    // Draw the world at a higher or lower resolution than the window
+    RenderScaleUp,
+    RenderScaleDown,
+    // Steps through the actions in `Action::ALL`, the next other button pressed
+    // replaces the bindings of the one it stopped at
+    Rebind,
+    Exit,
+}
+
+impl Action {
+    pub const ALL: [Action; 24] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Jump,
+        Action::Sprint,
+        Action::Crouch,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::Look,
+        Action::ToggleMouseLook,
+        Action::BreakBlock,
+        Action::PlaceBlock,
+        Action::ToggleFly,
+        Action::FreezeTime,
+        Action::TimeForward,
+        Action::TimeBackward,
+        Action::CycleMsaa,
+        Action::RenderScaleUp,
+        Action::RenderScaleDown,
+        Action::Rebind,
+        Action::Exit,
+    ];
+
+    // The one after this in `ALL`, wrapping around at the end
+    pub fn next(self) -> Action {
+        let index = Self::ALL.iter().position(|a| *a == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    ScrollUp,
+    ScrollDown,
+}
+
+// Everything the simulation reacts to, stripped of window and device ids so it
+// can be recorded and replayed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Button(Binding, ElementState),
+    // Scrolling is positive upwards and measured in lines
+    Scroll(f32),
+    MouseMotion(f64, f64),
+    FocusLost,
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => Some(Self::Button(Binding::Key(*key), *state)),
+            WindowEvent::MouseInput { button, state, .. } => {
+                Some(Self::Button(Binding::Mouse(*button), *state))
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => {
+                        *y as f32 / PIXELS_PER_LINE
+                    }
+                };
+                Some(Self::Scroll(lines))
+            }
+            WindowEvent::Focused(false) => Some(Self::FocusLost),
+            _ => None,
+        }
+    }
+}
+
+// Maps every action to any number of bindings. When deserializing, actions that
+// aren't mentioned keep their default bindings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    from = "HashMap<Action, Vec<Binding>>",
+    into = "HashMap<Action, Vec<Binding>>"
+)]
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Binding::*;
+        use VirtualKeyCode as K;
+
+        let bindings = HashMap::from([
+            (Action::MoveForward, vec![Key(K::W), Key(K::Up)]),
+            (Action::MoveBackward, vec![Key(K::S), Key(K::Down)]),
+            (Action::MoveLeft, vec![Key(K::A), Key(K::Left)]),
+            (Action::MoveRight, vec![Key(K::D), Key(K::Right)]),
+            (Action::MoveUp, vec![Key(K::Space)]),
+            (Action::MoveDown, vec![Key(K::LShift)]),
+            (Action::Jump, vec![Key(K::Space)]),
+            (Action::Sprint, vec![Key(K::LControl)]),
+            (Action::Crouch, vec![Key(K::LShift)]),
+            (Action::ZoomIn, vec![ScrollUp]),
+            (Action::ZoomOut, vec![ScrollDown]),
+            (Action::Look, vec![Mouse(MouseButton::Left)]),
+            (Action::ToggleMouseLook, vec![Key(K::Tab)]),
+            (Action::BreakBlock, vec![Mouse(MouseButton::Left)]),
+            (Action::PlaceBlock, vec![Mouse(MouseButton::Right)]),
+            (Action::ToggleFly, vec![Key(K::F)]),
+            (Action::FreezeTime, vec![Key(K::T)]),
+            (Action::TimeForward, vec![Key(K::RBracket)]),
+            (Action::TimeBackward, vec![Key(K::LBracket)]),
+            (Action::CycleMsaa, vec![Key(K::M)]),
+            (Action::RenderScaleUp, vec![Key(K::Equals)]),
+            (Action::RenderScaleDown, vec![Key(K::Minus)]),
+            (Action::Rebind, vec![Key(K::F1)]),
+            (Action::Exit, vec![Key(K::Escape)]),
+        ]);
+        Self { bindings }
+    }
+}
+
+impl From<HashMap<Action, Vec<Binding>>> for InputMap {
+    fn from(overrides: HashMap<Action, Vec<Binding>>) -> Self {
+        let mut map = Self::default();
+        map.bindings.extend(overrides);
+        map
+    }
+}
+
+impl From<InputMap> for HashMap<Action, Vec<Binding>> {
+    fn from(map: InputMap) -> Self {
+        map.bindings
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    // Adds a binding to an action, keeping the ones it already had
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    // Replaces all bindings of an action with a single one
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        self.bindings.insert(action, vec![binding]);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Input {
+    pub map: InputMap,
+    held: HashSet<Binding>,
+    just_pressed: HashSet<Action>,
+    scroll: HashMap<Action, f32>,
+    rebinding: Option<Action>,
+}
+
+impl Input {
+    pub fn new(map: InputMap) -> Self {
+        Self {
+            map,
+            ..Default::default()
+        }
+    }
+
+    // Returns true if the event was used by an action
+    pub fn process_event(&mut self, event: InputEvent) -> bool {
+        match event {
+            InputEvent::Button(binding, state) => self.process_binding(binding, state),
+            InputEvent::Scroll(lines) => self.process_scroll(lines),
+            // Keys that are released while the window is unfocused never send a
+            // release event, so forget everything to avoid stuck actions
+            InputEvent::FocusLost => {
+                self.held.clear();
+                false
+            }
+            InputEvent::MouseMotion(..) => false,
+        }
+    }
+
+    pub fn process_binding(&mut self, binding: Binding, state: ElementState) -> bool {
+        let pressed = state == ElementState::Pressed;
+        // Keys held since before a rebind started still have to be let go of
+        if !pressed {
+            self.held.remove(&binding);
+        }
+        if let Some(action) = self.rebinding {
+            if pressed {
+                self.map.rebind(action, binding);
+                self.rebinding = None;
+            }
+            return true;
+        }
+
+        if pressed && self.held.insert(binding) {
+            self.just_pressed.extend(self.map.actions(binding));
+        }
+        self.map.actions(binding).next().is_some()
+    }
+
+    pub fn process_scroll(&mut self, lines: f32) -> bool {
+        let (binding, amount) = if lines >= 0.0 {
+            (Binding::ScrollUp, lines)
+        } else {
+            (Binding::ScrollDown, -lines)
+        };
+        if let Some(action) = self.rebinding.take() {
+            self.map.rebind(action, binding);
+            return true;
+        }
+
+        let mut used = false;
+        for action in self.map.actions(binding) {
+            *self.scroll.entry(action).or_default() += amount;
+            self.just_pressed.insert(action);
+            used = true;
+        }
+        used
+    }
+
+    // The next key, mouse button or scroll will replace the bindings of `action`
+    pub fn start_rebind(&mut self, action: Action) {
+        self.rebinding = Some(action);
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    // The action waiting for a new binding
+    pub fn rebind_target(&self) -> Option<Action> {
+        self.rebinding
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|binding| self.held.contains(binding))
+    }
+
+    // True if the action was triggered since the last call to `end_frame`
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    // Number of scrolled lines that went to this action since the last frame
+    pub fn scroll(&self, action: Action) -> f32 {
+        self.scroll.get(&action).copied().unwrap_or(0.0)
+    }
+
+    pub fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.scroll.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VirtualKeyCode as K;
+
+    fn press(input: &mut Input, key: VirtualKeyCode) -> bool {
+        input.process_event(InputEvent::Button(Binding::Key(key), ElementState::Pressed))
+    }
+
+    fn release(input: &mut Input, key: VirtualKeyCode) -> bool {
+        input.process_event(InputEvent::Button(
+            Binding::Key(key),
+            ElementState::Released,
+        ))
+    }
+
+    #[test]
+    fn either_binding_triggers_an_action() {
+        let mut input = Input::new(InputMap::default());
+        assert!(press(&mut input, K::Up));
+        assert!(input.is_pressed(Action::MoveForward));
+        assert!(input.just_pressed(Action::MoveForward));
+
+        release(&mut input, K::Up);
+        input.end_frame();
+        assert!(!input.is_pressed(Action::MoveForward));
+        assert!(!input.just_pressed(Action::MoveForward));
+
+        press(&mut input, K::W);
+        assert!(input.is_pressed(Action::MoveForward));
+        // Still held while one of the two is
+        press(&mut input, K::Up);
+        release(&mut input, K::W);
+        assert!(input.is_pressed(Action::MoveForward));
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_button() {
+        let mut input = Input::new(InputMap::default());
+        input.start_rebind(Action::Jump);
+        assert!(input.is_rebinding());
+        // Used up by the rebind instead of moving forward
+        assert!(press(&mut input, K::W));
+        assert!(!input.is_rebinding());
+        assert!(!input.just_pressed(Action::MoveForward));
+        assert_eq!(input.map.bindings(Action::Jump), [Binding::Key(K::W)]);
+
+        release(&mut input, K::W);
+        press(&mut input, K::Space);
+        assert!(!input.is_pressed(Action::Jump));
+        press(&mut input, K::W);
+        assert!(input.is_pressed(Action::Jump));
+    }
+
+    #[test]
+    fn releasing_a_key_during_a_rebind() {
+        let mut input = Input::new(InputMap::default());
+        press(&mut input, K::W);
+        input.start_rebind(Action::Jump);
+        release(&mut input, K::W);
+        press(&mut input, K::J);
+        release(&mut input, K::J);
+        assert!(!input.is_pressed(Action::MoveForward));
+    }
+
+    #[test]
+    fn every_action_has_default_bindings() {
+        let map = InputMap::default();
+        assert_eq!(map.bindings.len(), Action::ALL.len());
+        assert!(Action::ALL
+            .iter()
+            .all(|action| !map.bindings(*action).is_empty()));
+        assert_eq!(Action::Exit.next(), Action::ALL[0]);
+    }
+
+    #[test]
+    fn scrolling_adds_up_until_the_end_of_the_frame() {
+        let mut input = Input::new(InputMap::default());
+        assert!(input.process_event(InputEvent::Scroll(1.5)));
+        assert!(input.process_event(InputEvent::Scroll(0.5)));
+        assert!(input.process_event(InputEvent::Scroll(-1.0)));
+        assert_eq!(input.scroll(Action::ZoomIn), 2.0);
+        assert_eq!(input.scroll(Action::ZoomOut), 1.0);
+        assert!(input.just_pressed(Action::ZoomIn));
+
+        input.end_frame();
+        assert_eq!(input.scroll(Action::ZoomIn), 0.0);
+        assert!(!input.just_pressed(Action::ZoomIn));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = Input::new(InputMap::default());
+        press(&mut input, K::W);
+        press(&mut input, K::LShift);
+        input.process_event(InputEvent::FocusLost);
+        assert!(!input.is_pressed(Action::MoveForward));
+        assert!(!input.is_pressed(Action::Crouch));
+
+        // Pressing it again afterwards works as normal
+        press(&mut input, K::W);
+        assert!(input.is_pressed(Action::MoveForward));
+    }
+}
@@ -1,10 +1,117 @@
-use cgmath::{InnerSpace, Rotation3, Zero};
+use std::{collections::HashMap, ops::Range};
+
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3, Rotation3, Vector3, Zero};
 use wgpu::{util::DeviceExt, Buffer, Device};
 
-pub const INST_PER_ROW: usize = 1414;
+use crate::{
+    ao,
+    block::{BlockId, Material, Transparency, AIR},
+    block_textures::BlockTextures,
+    lighting::Light,
+    lod::{self, LOD_LEVELS},
+    physics::Aabb,
+    world::{Chunk, World, CHUNK_SIZE, NEIGHBOURS},
+};
+
 pub struct Instance {
     pub pos: cgmath::Vector3<f32>,
     pub rot: cgmath::Quaternion<f32>,
+    // Size in blocks
+    pub scale: f32,
+    // Texture array layer of each face, see BlockTextures
+    pub layers: [u32; 6],
+    // Ambient occlusion at the corners of each face, see ao.rs
+    pub ao: [[u8; 4]; 6],
+    // Light of the block in front of each face, see lighting.rs
+    pub light: [Light; 6],
+    // Faces that are covered up and left out when drawing
+    pub hidden: [bool; 6],
+    pub material: Material,
+}
+
+// The instances of one chunk for every kind of transparency at every level of
+// detail, and the box around them
+#[derive(Debug, Clone)]
+pub struct ChunkInstances {
+    pub pos: Vector3<i32>,
+    pub aabb: Aabb,
+    // Indexed by transparency, then level of detail
+    pub lods: [[Range<u32>; LOD_LEVELS]; 3],
+}
+
+impl ChunkInstances {
+    // Instances at the level of detail for the distance from the eye to the
+    // closest point of the chunk
+    pub fn lod_instances(
+        &self,
+        eye: Point3<f32>,
+        lod_distance: f32,
+        transparency: Transparency,
+    ) -> Range<u32> {
+        let closest = eye
+            .zip(self.aabb.min, f32::max)
+            .zip(self.aabb.max, f32::min);
+        let level = lod::level(eye.distance(closest), lod_distance);
+        self.lods[transparency as usize][level].clone()
+    }
+
+    pub fn has_translucent(&self) -> bool {
+        self.lods[Transparency::Translucent as usize]
+            .iter()
+            .any(|instances| !instances.is_empty())
+    }
+}
+
+// How far the eye can move before translucent instances are sorted again
+const RESORT_DISTANCE: f32 = 1.0;
+
+// Translucent instances come last in the instance buffer and have to be drawn
+// back to front, so a copy of them is kept to sort when the eye moves
+pub struct TranslucentInstances {
+    // Index of the first one in the instance buffer
+    first: u32,
+    centres: Vec<Point3<f32>>,
+    raw: Vec<InstanceRaw>,
+    // Where the eye was when each range was last sorted, by its first instance
+    sorted_from: HashMap<u32, Point3<f32>>,
+}
+
+impl TranslucentInstances {
+    fn new(first: u32, centres: Vec<Point3<f32>>, raw: Vec<InstanceRaw>) -> Self {
+        Self {
+            first,
+            centres,
+            raw,
+            sorted_from: HashMap::new(),
+        }
+    }
+
+    // Sorts the instances in the range so the farthest from the eye comes first.
+    // Returns them to upload in place of the range, or None if they were sorted
+    // from close enough already
+    pub fn sort(&mut self, range: Range<u32>, eye: Point3<f32>) -> Option<&[InstanceRaw]> {
+        let sorted_from = self.sorted_from.get(&range.start);
+        if sorted_from.is_some_and(|from| from.distance(eye) < RESORT_DISTANCE) {
+            return None;
+        }
+        self.sorted_from.insert(range.start, eye);
+        let local = (range.start - self.first) as usize..(range.end - self.first) as usize;
+        let centres = &mut self.centres[local.clone()];
+        let raw = &mut self.raw[local];
+
+        let mut order = (0..centres.len()).collect::<Vec<_>>();
+        let distances = centres.iter().map(|c| c.distance2(eye)).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| distances[b].total_cmp(&distances[a]));
+        let sorted = order
+            .iter()
+            .map(|&i| (centres[i], raw[i]))
+            .collect::<Vec<_>>();
+        for (i, (centre, instance)) in sorted.into_iter().enumerate() {
+            centres[i] = centre;
+            raw[i] = instance;
+        }
+        Some(raw)
+    }
 }
 
 #[repr(C)]
@@ -12,6 +119,12 @@ pub struct Instance {
 pub struct InstanceRaw {
     //Data that will go in buffer
     model: [[f32; 4]; 4],
+    // The face layers packed into bytes, four per u32
+    layers: [u32; 2],
+    // See pack_ao
+    ao: [u32; 2],
+    // The face lights packed like the layers
+    light: [u32; 2],
 }
 
 impl Instance {
@@ -25,32 +138,74 @@ impl Instance {
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.pos)
                 * cgmath::Matrix4::from(self.rot)
-                * cgmath::Matrix4::from_scale(0.5))
+                * cgmath::Matrix4::from_scale(0.5 * self.scale))
             .into(),
+            layers: pack_bytes(self.layers),
+            ao: pack_ao(self.ao, self.hidden, self.material),
+            light: pack_bytes(self.light.map(|light| light.to_byte() as u32)),
         }
     }
 
-    pub fn create_instances(device: &Device) -> (Vec<Instance>, Buffer) {
-        use noise::utils::{NoiseMapBuilder, PlaneMapBuilder};
-        use noise::{Fbm, Perlin};
-        let fbm = Fbm::<Perlin>::new(0);
+    fn centre(&self) -> Point3<f32> {
+        // See create_instances for where the cube sits around its position
+        Point3::new(self.pos.x, self.pos.y, self.pos.z + 0.5 * self.scale)
+    }
 
-        let p = PlaneMapBuilder::<_, 2>::new(&fbm)
-            .set_size(INST_PER_ROW, INST_PER_ROW)
-            .build();
+    // Instances are grouped per chunk and level of detail, so whole chunks can
+    // be skipped or swapped for a coarser version when drawing. All chunks at one
+    // level come before the next level, so neighbouring chunks at the same level
+    // can still be drawn together. Each kind of transparency is drawn in its
+    // own pass and gets its own part of the buffer, translucent last
+    pub fn create_instances(
+        device: &Device,
+        world: &World,
+        textures: &BlockTextures,
+    ) -> (Vec<ChunkInstances>, Buffer, TranslucentInstances) {
+        let mut chunk_positions = world.chunks().map(|(pos, _)| *pos).collect::<Vec<_>>();
+        chunk_positions.sort_by_key(|pos| (pos.x, pos.z, pos.y));
+
+        let mut levels = vec![];
+        for chunk_pos in chunk_positions {
+            let Some(chunk) = world.chunk(chunk_pos) else {
+                continue;
+            };
+            let (blocks, shading): (Vec<_>, Vec<_>) = exposed_blocks(world, chunk_pos, chunk)
+                .into_iter()
+                .map(|(pos, block, shading)| ((pos, block), shading))
+                .unzip();
+            if blocks.is_empty() {
+                continue;
+            }
+            let [level_1, level_2, level_3] = lod::exposed_cells(world, chunk_pos, chunk);
+            levels.push((chunk_pos, shading, [blocks, level_1, level_2, level_3]));
+        }
 
         let mut instances = vec![];
-        for z in 0..INST_PER_ROW {
-            for y in 0..1 {
-                for x in 0..INST_PER_ROW {
-                    let v = p.get_value(x, z) + 1.006;
-              
-                    let mut pos = cgmath::Vector3 {
-                        x: x as f32,
-                        y: (v * 100.) as u32 as f32 + y as f32,
-                        z: z as f32,
+        let mut lods: Vec<[[Range<u32>; LOD_LEVELS]; 3]> = vec![Default::default(); levels.len()];
+        let mut aabbs: Vec<Option<Aabb>> = vec![None; levels.len()];
+        for (transparency, level) in Transparency::ALL
+            .into_iter()
+            .flat_map(|t| (0..LOD_LEVELS).map(move |level| (t, level)))
+        {
+            let scale = (1 << level) as f32;
+            for (i, (_, shading, cells)) in levels.iter().enumerate() {
+                let start = instances.len() as u32;
+                for (j, (cell, block)) in cells[level].iter().enumerate() {
+                    if world.blocks.get(*block).transparency != transparency {
+                        continue;
+                    }
+                    // Coarser cells can stick out of the blocks they stand for
+                    let min = Point3::from_vec(cell.cast::<f32>().unwrap());
+                    let cell_aabb = Aabb::new(min, min + Vector3::new(scale, scale, scale));
+                    aabbs[i] = Some(aabbs[i].map_or(cell_aabb, |aabb| aabb.union(&cell_aabb)));
+
+                    // The cube model spans -1..1 on x and y and 0..2 on z, so after
+                    // scaling it by half this lines it up with the voxel it represents
+                    let pos = cgmath::Vector3 {
+                        x: cell.x as f32 + 0.5 * scale,
+                        y: cell.y as f32 + 0.5 * scale,
+                        z: cell.z as f32,
                     };
-                    // pos.y = pos.y * 40. + y as f32;
 
                     let rotation = if pos.is_zero() {
                         // this is needed so an object at (0, 0, 0) won't get scaled to zero
@@ -63,11 +218,45 @@ impl Instance {
                         cgmath::Quaternion::from_axis_angle(pos.normalize(), cgmath::Deg(0.0))
                     };
 
-                    instances.push(Instance { pos, rot: rotation });
+                    // Coarser cells are far enough away that occlusion wouldn't show,
+                    // they're lit as if out in the open
+                    let (ao, light, hidden) = if level == 0 {
+                        shading[j]
+                    } else {
+                        (ao::NO_OCCLUSION, [Light::SKY; 6], [false; 6])
+                    };
+                    instances.push(Instance {
+                        pos,
+                        rot: rotation,
+                        scale,
+                        layers: textures.faces(*block),
+                        ao,
+                        light,
+                        hidden,
+                        material: world.blocks.get(*block).material,
+                    });
                 }
+                lods[i][transparency as usize][level] = start..instances.len() as u32;
             }
         }
 
+        // Translucent instances start with the first chunk's at full detail
+        let first = lods.first().map_or(0, |lods| {
+            lods[Transparency::Translucent as usize][0].start as usize
+        });
+
+        let chunks = levels
+            .iter()
+            .zip(lods)
+            .zip(aabbs)
+            .map(|(((pos, _, _), lods), aabb)| ChunkInstances {
+                pos: *pos,
+                // Level 0 is never empty
+                aabb: aabb.unwrap(),
+                lods,
+            })
+            .collect();
+
         let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
@@ -75,8 +264,109 @@ impl Instance {
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
-        (instances, instance_buffer)
+        let translucent = TranslucentInstances::new(
+            first as u32,
+            instances[first..].iter().map(Instance::centre).collect(),
+            instance_data[first..].to_vec(),
+        );
+
+        (chunks, instance_buffer, translucent)
+    }
+}
+
+// Ambient occlusion, light and whether it's hidden of every face of a block
+type Shading = ([[u8; 4]; 6], [Light; 6], [bool; 6]);
+
+// World positions of the blocks in a chunk that have at least one face that
+// can be seen through a neighbouring block, what block they are and the
+// shading of their faces
+fn exposed_blocks(
+    world: &World,
+    chunk_pos: Vector3<i32>,
+    chunk: &Chunk,
+) -> Vec<(Vector3<i32>, BlockId, Shading)> {
+    if let Chunk::Uniform(AIR) = chunk {
+        return vec![];
+    }
+
+    let origin = chunk_pos * CHUNK_SIZE;
+    let get = |local: Vector3<i32>| {
+        if (0..CHUNK_SIZE).contains(&local.x)
+            && (0..CHUNK_SIZE).contains(&local.y)
+            && (0..CHUNK_SIZE).contains(&local.z)
+        {
+            chunk.get(local)
+        } else {
+            world.get_block(origin + local)
+        }
+    };
+    // Faces between two blocks of the same kind, like water next to water, are hidden
+    let shows_face = |block: BlockId, neighbour: Vector3<i32>| {
+        let other = get(neighbour);
+        other != block && !world.blocks.get(other).opaque && (origin + neighbour).y >= 0
+    };
+
+    let mut exposed = vec![];
+    let mut visit = |local: Vector3<i32>| {
+        let block = chunk.get(local);
+        if block != AIR && NEIGHBOURS.iter().any(|n| shows_face(block, local + n)) {
+            // Looked up through the chunk rather than the world where possible
+            let ao = ao::block_ao(
+                |pos| world.blocks.get(get(pos - origin)).opaque,
+                origin + local,
+            );
+            let light = NEIGHBOURS.map(|n| world.light.get(origin + local + n));
+            let hidden = NEIGHBOURS.map(|n| !shows_face(block, local + n));
+            exposed.push((origin + local, block, (ao, light, hidden)));
+        }
+    };
+
+    // Only the outer shell of a uniform chunk can border a different block
+    let shell_only = matches!(chunk, Chunk::Uniform(_));
+    let on_shell = |c: i32| c == 0 || c == CHUNK_SIZE - 1;
+
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                if shell_only && !(on_shell(x) || on_shell(y) || on_shell(z)) {
+                    continue;
+                }
+                visit(Vector3::new(x, y, z));
+            }
+        }
     }
+    exposed
+}
+
+// A byte per face, four to a u32. The texture array has at most 256 layers, so
+// each fits in one
+fn pack_bytes(bytes: [u32; 6]) -> [u32; 2] {
+    let mut packed = [0; 2];
+    for (face, byte) in bytes.iter().enumerate() {
+        packed[face / 4] |= (byte & 0xff) << (face % 4 * 8);
+    }
+    packed
+}
+
+// Two bits per corner, 16 corners in the first u32 and 8 in the second. The top
+// 16 bits of the second hold whether each face's quad is flipped, then whether
+// each face is hidden, then the material in the last four
+fn pack_ao(ao: [[u8; 4]; 6], hidden: [bool; 6], material: Material) -> [u32; 2] {
+    let mut packed = [0; 2];
+    for (face, corners) in ao.iter().enumerate() {
+        for (corner, value) in corners.iter().enumerate() {
+            let i = face * 4 + corner;
+            packed[i / 16] |= (*value as u32 & 3) << (i % 16 * 2);
+        }
+        if ao::flip_quad(*corners) {
+            packed[1] |= 1 << (16 + face);
+        }
+        if hidden[face] {
+            packed[1] |= 1 << (22 + face);
+        }
+    }
+    packed[1] |= (material as u32) << 28;
+    packed
 }
 
 impl InstanceRaw {
@@ -114,7 +404,98 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
             ],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translucent(centres: &[(f32, f32, f32)]) -> TranslucentInstances {
+        let centres = centres.iter().map(|&c| Point3::from(c)).collect::<Vec<_>>();
+        let raw = centres
+            .iter()
+            .map(|c| InstanceRaw {
+                model: cgmath::Matrix4::from_translation(c.to_vec()).into(),
+                layers: [0; 2],
+                ao: [0; 2],
+                light: [0; 2],
+            })
+            .collect();
+        TranslucentInstances::new(10, centres, raw)
+    }
+
+    #[test]
+    fn translucent_instances_sort_back_to_front() {
+        let mut instances = translucent(&[(0.0, 0.0, 0.0), (5.0, 0.0, 0.0), (2.0, 0.0, 0.0)]);
+        let eye = Point3::new(-1.0, 0.0, 0.0);
+        let sorted = instances.sort(10..13, eye).unwrap();
+        // The raw instances move along with their centres
+        let xs = sorted.iter().map(|raw| raw.model[3][0]).collect::<Vec<_>>();
+        assert_eq!(xs, [5.0, 2.0, 0.0]);
+
+        // Seen from the other side the order flips, but only once the eye has
+        // moved far enough
+        assert!(instances
+            .sort(10..13, Point3::new(-0.5, 0.0, 0.0))
+            .is_none());
+        let sorted = instances.sort(10..13, Point3::new(6.0, 0.0, 0.0)).unwrap();
+        let xs = sorted.iter().map(|raw| raw.model[3][0]).collect::<Vec<_>>();
+        assert_eq!(xs, [0.0, 2.0, 5.0]);
+    }
+
+    #[test]
+    fn small_moves_add_up() {
+        let mut instances = translucent(&[(0.0, 0.0, 0.0), (5.0, 0.0, 0.0)]);
+        assert!(instances.sort(10..12, Point3::new(0.0, 0.0, 0.0)).is_some());
+        assert!(instances.sort(10..12, Point3::new(0.6, 0.0, 0.0)).is_none());
+        // Less than the resort distance since the last call, but not since the
+        // last sort
+        assert!(instances.sort(10..12, Point3::new(1.2, 0.0, 0.0)).is_some());
+        assert!(instances.sort(10..12, Point3::new(1.8, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn ranges_are_sorted_on_their_own() {
+        let mut instances = translucent(&[(3.0, 0.0, 0.0), (1.0, 0.0, 0.0), (9.0, 0.0, 0.0)]);
+        let eye = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(instances.sort(11..13, eye).unwrap().len(), 2);
+        // The first one is outside the range and stays put
+        assert_eq!(instances.centres[0], Point3::new(3.0, 0.0, 0.0));
+        assert_eq!(instances.centres[1], Point3::new(9.0, 0.0, 0.0));
+        // Other ranges haven't been sorted from anywhere yet
+        assert!(instances.sort(10..11, eye).is_some());
+    }
+
+    #[test]
+    fn flags_and_material_stay_clear_of_the_ao() {
+        let mut hidden = [false; 6];
+        hidden[2] = true;
+        let packed = pack_ao([[3; 4]; 6], hidden, Material::Water);
+        // Every corner of every face
+        assert_eq!(packed[0], u32::MAX);
+        assert_eq!(packed[1] & 0xffff, 0xffff);
+        // The top face is hidden, and the shader finds the material in the top bits
+        assert_eq!(packed[1] >> 22 & 0x3f, 1 << 2);
+        assert_eq!(packed[1] >> 28, Material::Water as u32);
+        // No quads are flipped with the same occlusion at every corner
+        assert_eq!(packed[1] >> 16 & 0x3f, 0);
+    }
+}
@@ -3,42 +3,105 @@ use winit::{
     dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-use wgpu::{include_wgsl, Device, Queue, RenderPipeline, Surface, SurfaceConfiguration};
+use wgpu::{Device, Queue, RenderPipeline, Surface, SurfaceConfiguration};
 
-use crate::{instance::InstanceRaw, vertices::Vertex};
+use crate::{
+    block::Transparency, input::InputEvent, instance::InstanceRaw, replay::Recording,
+    vertices::Vertex,
+};
 use std::time::Instant;
 
+mod ao;
+mod block;
+mod block_textures;
 mod camera;
+pub mod config;
+mod day_night;
 mod engine;
+mod fog;
+mod frustum;
+mod gpu_cull;
+mod hot_reload;
+pub mod input;
 mod instance;
+mod light;
+mod lighting;
+mod lod;
+mod physics;
+mod player;
+mod render_target;
+pub mod replay;
+mod resource_pack;
+mod shadow;
+mod simulation;
+mod sky;
+mod skybox;
 mod texture;
+mod timestep;
 mod vertices;
+mod visibility;
+mod water;
+mod world;
 
-use engine::Engine;
-
-pub const WINDOW_SIZE: PhysicalSize<u32> = PhysicalSize::new(1000, 1000);
+use config::Config;
+// For driving the engine from your own event loop, like `run` does
+pub use engine::Engine;
+pub use light::Sun;
 
-pub async fn run() {
+pub async fn run(mut config: Config) -> anyhow::Result<()> {
     env_logger::init();
+
+    // A replay runs with the settings it was recorded with, apart from the window
+    let replay = match &config.replay {
+        Some(path) => {
+            let recording = Recording::load(path)?;
+            config = Config {
+                window: config.window.clone(),
+                record: config.record.clone(),
+                replay: config.replay.clone(),
+                headless: config.headless,
+                ..recording.config.clone()
+            };
+            Some(recording)
+        }
+        None => None,
+    };
+
+    if config.headless {
+        if let Some(recording) = replay {
+            let sim = replay::run_headless(recording);
+            println!("time: {:?}", sim.time);
+            println!("camera: {:?}", sim.camera);
+        }
+        return Ok(());
+    }
+
     let event_loop = EventLoop::new();
+    let fullscreen = config
+        .window
+        .fullscreen
+        .then_some(Fullscreen::Borderless(None));
     let window = WindowBuilder::new()
-        .with_inner_size(WINDOW_SIZE)
+        .with_title("voxel")
+        .with_inner_size(PhysicalSize::new(config.window.width, config.window.height))
+        .with_fullscreen(fullscreen)
         .build(&event_loop)
         .unwrap();
 
-    let mut state = Engine::new(&window).await;
+    let mut state = Engine::new(&window, &config, replay).await?;
     let mut last_render_time = std::time::Instant::now();
+    let mut last_title_update = last_render_time;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion{ delta, },
                 .. // We're not using device_id currently
-            } => if state.mouse_pressed {
-                state.camera_controller.process_mouse(delta.0, delta.1)
+            } => {
+                state.process_event(InputEvent::MouseMotion(delta.0, delta.1));
             }
 
             Event::WindowEvent {
@@ -46,17 +109,8 @@ pub async fn run() {
                 window_id,
             } if window_id == window.id() && !state.input(event) => {
                 match event {
-                    #[cfg(not(target_arch="wasm32"))]
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::Resized(physical_size) => {
                         state.resize(*physical_size);
                     }
@@ -80,12 +134,28 @@ pub async fn run() {
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e),
                 }
+
+                if now - last_title_update >= std::time::Duration::from_secs(1) {
+                    last_title_update = now;
+                    match state.chunk_stats() {
+                        Some(stats) => window.set_title(&format!(
+                            "voxel - chunks drawn: {} culled: {} occluded: {}",
+                            stats.drawn, stats.culled, stats.occluded
+                        )),
+                        None => window.set_title("voxel - chunks culled on the GPU"),
+                    }
+                }
             }
             Event::RedrawEventsCleared => {
+                if state.sim.exit_requested {
+                    *control_flow = ControlFlow::Exit;
+                }
+                state.update_cursor(&window);
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
                 window.request_redraw();
             }
+            Event::LoopDestroyed => state.save_recording(),
             _ => {}
         }
     });
@@ -102,12 +172,15 @@ pub struct Manager {
 impl Manager {
     pub async fn set_wgpu_up(
         window: &Window,
+        vsync: bool,
+        optional_features: wgpu::Features,
     ) -> (
         Surface,
         Device,
         Queue,
         SurfaceConfiguration,
         PhysicalSize<u32>,
+        Vec<u32>,
     ) {
         let size = window.inner_size();
         let inst_descriptor = wgpu::InstanceDescriptor {
@@ -129,7 +202,11 @@ impl Manager {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // Lets the adapter say which formats support 2 and 8
+                    // samples per pixel, otherwise only 4 can be used
+                    features: adapter.features()
+                        & (optional_features
+                            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: wgpu::Limits {
@@ -148,37 +225,90 @@ impl Manager {
             .formats
             .iter()
             .copied()
-            .filter(|f| f.is_srgb())
-            .next()
+            .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
 
+        let present_mode = if vsync {
+            wgpu::PresentMode::AutoVsync
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        };
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
         surface.configure(&device, &config);
 
-        (surface, device, queue, config, size)
+        let format_features = |format: wgpu::TextureFormat| {
+            if device
+                .features()
+                .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            {
+                adapter.get_texture_format_features(format)
+            } else {
+                format.guaranteed_format_features(device.features())
+            }
+        };
+        // Samples per pixel the surface and depth formats can both be drawn with
+        let colour = format_features(surface_format).flags;
+        let depth = format_features(DEPTH_FORMAT).flags;
+        let sample_counts = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                colour.sample_count_supported(count)
+                    && depth.sample_count_supported(count)
+                    && (count == 1
+                        || colour.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+            })
+            .collect();
+
+        (surface, device, queue, config, size, sample_counts)
+    }
+
+    // Compiles a shader, reporting mistakes in it as an error instead of
+    // panicking since it may come from a resource pack
+    pub async fn create_shader_module(
+        device: &Device,
+        name: &str,
+        source: String,
+    ) -> anyhow::Result<wgpu::ShaderModule> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        match device.pop_error_scope().await {
+            Some(error) => anyhow::bail!("failed to compile {}: {}", name, error),
+            None => Ok(module),
+        }
     }
 
     pub fn create_render_pipeline(
         device: &Device,
         config: &SurfaceConfiguration,
-        tex_layout: &wgpu::BindGroupLayout,
-        cam_layout: &wgpu::BindGroupLayout,
-        count_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        // Block textures, camera, water and light, in that order
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        transparency: Transparency,
+        sample_count: u32,
     ) -> RenderPipeline {
-        let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
-
+        // Translucent blocks are drawn last over everything else, without
+        // hiding what's further back in the same pass
+        let (entry_point, blend, depth_write_enabled) = match transparency {
+            Transparency::Opaque => ("fs_main", wgpu::BlendState::REPLACE, true),
+            Transparency::Cutout => ("fs_cutout", wgpu::BlendState::REPLACE, true),
+            Transparency::Translucent => ("fs_main", wgpu::BlendState::ALPHA_BLENDING, false),
+        };
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[tex_layout, cam_layout, count_layout],
+                bind_group_layouts,
                 push_constant_ranges: &[],
             });
 
@@ -186,16 +316,16 @@ impl Manager {
             label: Some("Render Pipeline"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[Vertex::desc(), InstanceRaw::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
+                module: shader,
+                entry_point,
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -214,12 +344,15 @@ impl Manager {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
-            multisample: wgpu::MultisampleState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
             multiview: None, // 5.
         });
         render_pipeline
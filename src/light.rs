@@ -0,0 +1,58 @@
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+// Directional light that every face is shaded with
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sun {
+    /// Direction towards the sun at noon, doesn't have to be normalized.
+    /// Default: (0.4, 1.0, 0.3)
+    pub direction: (f32, f32, f32),
+    /// Colour of the sunlight, linear RGB. Default: (1.0, 0.95, 0.85)
+    pub colour: (f32, f32, f32),
+    /// Light that reaches faces turned away from the sun. Default: (0.35, 0.38, 0.45)
+    pub ambient: (f32, f32, f32),
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            direction: (0.4, 1.0, 0.3),
+            colour: (1.0, 0.95, 0.85),
+            ambient: (0.35, 0.38, 0.45),
+        }
+    }
+}
+
+impl Sun {
+    // Normalized direction towards the sun
+    pub fn direction(&self) -> Vector3<f32> {
+        let direction = Vector3::from(self.direction);
+        // Normalizing a zero vector gives NaN, light from straight above instead
+        if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    // vec4s to match the alignment of vec3 in WGSL, w is unused
+    direction: [f32; 4],
+    colour: [f32; 4],
+    ambient: [f32; 4],
+}
+
+impl From<&Sun> for LightUniform {
+    fn from(sun: &Sun) -> Self {
+        let vec4 = |(x, y, z): (f32, f32, f32)| [x, y, z, 0.0];
+        Self {
+            direction: sun.direction().extend(0.0).into(),
+            colour: vec4(sun.colour),
+            ambient: vec4(sun.ambient),
+        }
+    }
+}
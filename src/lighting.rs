@@ -0,0 +1,518 @@
+use std::collections::{HashMap, VecDeque};
+
+use cgmath::Vector3;
+
+use crate::{
+    block::AIR,
+    world::{chunk_pos, local_pos, Chunk, World, CHUNK_SIZE, CHUNK_VOLUME, NEIGHBOURS},
+};
+
+// Light levels go from 0 in the dark up to this in direct sunlight or on a
+// glowing block, and drop by one for every block they spread
+pub const MAX_LIGHT: u8 = 15;
+// Straight down in NEIGHBOURS, sunlight goes this way without fading
+const DOWN: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    // Light from the sky, which gets dimmer at night
+    Sky,
+    // Light given off by blocks like torches
+    Block,
+}
+
+// Both light levels at a block packed into a byte, skylight in the top four bits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Light(u8);
+
+impl Light {
+    pub const DARK: Self = Self(0);
+    // Out in the open, away from anything glowing
+    pub const SKY: Self = Self(MAX_LIGHT << 4);
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        Self((sky << 4) | (block & 0xf))
+    }
+
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    pub fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block(),
+        }
+    }
+
+    fn with(self, channel: Channel, level: u8) -> Self {
+        match channel {
+            Channel::Sky => Self::new(level, self.block()),
+            Channel::Block => Self::new(self.sky(), level),
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self.0
+    }
+}
+
+// Like Chunk, most chunks are lit the same all the way through
+#[derive(Debug, Clone)]
+enum LightChunk {
+    Uniform(Light),
+    Dense(Box<[Light; CHUNK_VOLUME]>),
+}
+
+impl LightChunk {
+    fn get(&self, local: Vector3<i32>) -> Light {
+        match self {
+            LightChunk::Uniform(light) => *light,
+            LightChunk::Dense(lights) => lights[Chunk::index(local)],
+        }
+    }
+
+    fn set(&mut self, local: Vector3<i32>, light: Light) {
+        if let LightChunk::Uniform(current) = *self {
+            if current == light {
+                return;
+            }
+            *self = LightChunk::Dense(Box::new([current; CHUNK_VOLUME]));
+        }
+        if let LightChunk::Dense(lights) = self {
+            lights[Chunk::index(local)] = light;
+        }
+    }
+}
+
+// Skylight and block light of every block in the world. Chunks that haven't
+// been lit are open sky, apart from below the world where it's dark
+#[derive(Debug, Default, Clone)]
+pub struct LightMap {
+    chunks: HashMap<Vector3<i32>, LightChunk>,
+}
+
+impl LightMap {
+    // Lights a whole world at once. Sunlight goes straight down every column
+    // until it hits an opaque block, then spreads sideways into caves and under
+    // overhangs while the light of every glowing block spreads out
+    pub fn new(world: &World) -> Self {
+        let mut tops: HashMap<(i32, i32), i32> = HashMap::new();
+        for (pos, _) in world.chunks() {
+            let top = tops.entry((pos.x, pos.z)).or_insert(pos.y);
+            *top = (*top).max(pos.y);
+        }
+
+        let mut map = Self::default();
+        let mut unlit = vec![];
+        let mut glowing = VecDeque::new();
+        let air = Chunk::Uniform(AIR);
+        for ((x, z), top) in tops {
+            // Whether sunlight has made it down to this layer, per block of the column
+            let mut sunlit = [true; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+            for y in (0..=top).rev() {
+                let pos = Vector3::new(x, y, z);
+                let chunk = world.chunk(pos).unwrap_or(&air);
+                let light = sunlight(world, pos, chunk, &mut sunlit, &mut unlit, &mut glowing);
+                map.chunks.insert(pos, light);
+            }
+        }
+
+        // Sunlight only comes in sideways where a block it didn't reach from
+        // above is next to one it did
+        let mut sky = VecDeque::new();
+        for pos in unlit {
+            for offset in NEIGHBOURS {
+                if map.get(pos + offset).sky() > 1 {
+                    sky.push_back(pos + offset);
+                }
+            }
+        }
+        map.spread(world, Channel::Sky, sky);
+        map.spread(world, Channel::Block, glowing);
+        map
+    }
+
+    pub fn get(&self, pos: Vector3<i32>) -> Light {
+        if pos.y < 0 {
+            return Light::DARK;
+        }
+        self.chunks
+            .get(&chunk_pos(pos))
+            .map_or(Light::SKY, |chunk| chunk.get(local_pos(pos)))
+    }
+
+    fn set(&mut self, pos: Vector3<i32>, channel: Channel, level: u8) {
+        if pos.y < 0 {
+            return;
+        }
+        let light = self.get(pos).with(channel, level);
+        self.chunks
+            .entry(chunk_pos(pos))
+            .or_insert(LightChunk::Uniform(Light::SKY))
+            .set(local_pos(pos), light);
+    }
+
+    // Relights around a block that was just placed or removed. Only the light
+    // that went through or came from the block is taken away and spread again,
+    // so this stays cheap however much of the world is lit
+    pub fn block_changed(&mut self, world: &World, pos: Vector3<i32>) {
+        for channel in [Channel::Sky, Channel::Block] {
+            let mut relight = VecDeque::new();
+            let level = self.get(pos).get(channel);
+            if level > 0 {
+                self.set(pos, channel, 0);
+                self.darken(world, channel, VecDeque::from([(pos, level)]), &mut relight);
+            }
+
+            let glow = world.block_type(pos).light;
+            if channel == Channel::Block && glow > 0 {
+                self.set(pos, channel, glow);
+                relight.push_back(pos);
+            }
+            // Light from around the block can come in now
+            if !world.is_opaque(pos) {
+                relight.extend(NEIGHBOURS.iter().map(|offset| pos + offset));
+            }
+            self.spread(world, channel, relight);
+        }
+    }
+
+    // Breadth first from every block in the queue, brightening the blocks
+    // around them that are darker than the light reaching them
+    fn spread(&mut self, world: &World, channel: Channel, mut queue: VecDeque<Vector3<i32>>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = self.get(pos).get(channel);
+            for (i, offset) in NEIGHBOURS.iter().enumerate() {
+                let next = pos + offset;
+                let reaching = spread_level(channel, i, level);
+                if reaching == 0 || world.is_opaque(next) || self.get(next).get(channel) >= reaching
+                {
+                    continue;
+                }
+                self.set(next, channel, reaching);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    // Takes away the light that spread out from the blocks in the queue, which
+    // have already been set to 0 and come with the level they used to have.
+    // Blocks lit from somewhere else are left for `spread` to light the rest
+    // back up from
+    fn darken(
+        &mut self,
+        world: &World,
+        channel: Channel,
+        mut queue: VecDeque<(Vector3<i32>, u8)>,
+        relight: &mut VecDeque<Vector3<i32>>,
+    ) {
+        while let Some((pos, level)) = queue.pop_front() {
+            for (i, offset) in NEIGHBOURS.iter().enumerate() {
+                let next = pos + offset;
+                let next_level = self.get(next).get(channel);
+                if next_level == 0 {
+                    continue;
+                }
+                if next_level < level || spread_level(channel, i, level) == MAX_LIGHT {
+                    self.set(next, channel, 0);
+                    queue.push_back((next, next_level));
+                    let glow = world.block_type(next).light;
+                    if channel == Channel::Block && glow > 0 {
+                        self.set(next, channel, glow);
+                        relight.push_back(next);
+                    }
+                } else {
+                    relight.push_back(next);
+                }
+            }
+        }
+    }
+}
+
+// Level light reaches a neighbour with, in the direction NEIGHBOURS[direction]
+fn spread_level(channel: Channel, direction: usize, level: u8) -> u8 {
+    if channel == Channel::Sky && direction == DOWN && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+// Lights a chunk with the sunlight coming straight down from the chunk above,
+// `sunlit` carries that from one chunk to the next. Blocks the sun doesn't
+// reach and glowing blocks are collected for spreading light afterwards
+fn sunlight(
+    world: &World,
+    chunk_pos: Vector3<i32>,
+    chunk: &Chunk,
+    sunlit: &mut [bool],
+    unlit: &mut Vec<Vector3<i32>>,
+    glowing: &mut VecDeque<Vector3<i32>>,
+) -> LightChunk {
+    if let Chunk::Uniform(block) = chunk {
+        let block = world.blocks.get(*block);
+        if block.light == 0 {
+            if block.opaque {
+                sunlit.fill(false);
+                return LightChunk::Uniform(Light::DARK);
+            }
+            if sunlit.iter().all(|sunlit| *sunlit) {
+                return LightChunk::Uniform(Light::SKY);
+            }
+        }
+    }
+
+    let origin = chunk_pos * CHUNK_SIZE;
+    let mut light = LightChunk::Uniform(Light::DARK);
+    for y in (0..CHUNK_SIZE).rev() {
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let local = Vector3::new(x, y, z);
+                let block = world.blocks.get(chunk.get(local));
+                let sunlit = &mut sunlit[(x + z * CHUNK_SIZE) as usize];
+                *sunlit &= !block.opaque;
+                let sky = if *sunlit { MAX_LIGHT } else { 0 };
+                light.set(local, Light::new(sky, block.light));
+
+                if !*sunlit && !block.opaque {
+                    unlit.push(origin + local);
+                }
+                if block.light > 0 {
+                    glowing.push_back(origin + local);
+                }
+            }
+        }
+    }
+    light
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockId, BlockType, DIRT};
+
+    fn torch(world: &mut World) -> BlockId {
+        world.blocks.register(BlockType {
+            light: 14,
+            ..BlockType::empty("torch")
+        })
+    }
+
+    // A dirt floor at y = 0 from -size to size on x and z
+    fn floor(world: &mut World, size: i32) {
+        for z in -size..=size {
+            for x in -size..=size {
+                world.set_block(Vector3::new(x, 0, z), DIRT);
+            }
+        }
+    }
+
+    // A closed dirt box around the blocks from min to max
+    fn room(world: &mut World, min: Vector3<i32>, max: Vector3<i32>) {
+        for z in min.z - 1..=max.z + 1 {
+            for y in min.y - 1..=max.y + 1 {
+                for x in min.x - 1..=max.x + 1 {
+                    let inside = (min.x..=max.x).contains(&x)
+                        && (min.y..=max.y).contains(&y)
+                        && (min.z..=max.z).contains(&z);
+                    if !inside {
+                        world.set_block(Vector3::new(x, y, z), DIRT);
+                    }
+                }
+            }
+        }
+    }
+
+    // Relights the whole world from scratch and checks every block around
+    // `center` against the incrementally updated light
+    fn assert_matches_full_relight(world: &World, center: Vector3<i32>, radius: i32) {
+        let full = LightMap::new(world);
+        for z in -radius..=radius {
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    let pos = center + Vector3::new(x, y, z);
+                    assert_eq!(world.light.get(pos), full.get(pos), "at {:?}", pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn open_sky_is_fully_lit() {
+        let mut world = World::new();
+        floor(&mut world, 4);
+        let light = LightMap::new(&world);
+        assert_eq!(light.get(Vector3::new(0, 1, 0)), Light::SKY);
+        assert_eq!(light.get(Vector3::new(3, 40, -2)), Light::SKY);
+        assert_eq!(light.get(Vector3::new(0, 0, 0)), Light::DARK);
+        assert_eq!(light.get(Vector3::new(0, -1, 0)), Light::DARK);
+    }
+
+    #[test]
+    fn sunlight_goes_straight_down_without_fading() {
+        let mut world = World::new();
+        // A one block wide shaft down through solid dirt
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(0, 40, 0));
+        world.set_block(Vector3::new(0, 41, 0), AIR);
+        let light = LightMap::new(&world);
+        for y in 1..=40 {
+            assert_eq!(
+                light.get(Vector3::new(0, y, 0)).sky(),
+                MAX_LIGHT,
+                "at y {}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn sunlight_spreads_under_a_roof() {
+        let mut world = World::new();
+        floor(&mut world, 8);
+        for z in -2..=2 {
+            for x in -2..=2 {
+                world.set_block(Vector3::new(x, 3, z), DIRT);
+            }
+        }
+        let light = LightMap::new(&world);
+        // Blocks under the roof are lit from the open edge, one level less per step
+        assert_eq!(light.get(Vector3::new(2, 1, 0)).sky(), 14);
+        assert_eq!(light.get(Vector3::new(0, 1, 0)).sky(), 12);
+        assert_eq!(light.get(Vector3::new(0, 2, 0)).sky(), 12);
+        assert_eq!(light.get(Vector3::new(3, 1, 0)).sky(), MAX_LIGHT);
+    }
+
+    #[test]
+    fn sealed_rooms_are_dark() {
+        let mut world = World::new();
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(3, 3, 3));
+        let light = LightMap::new(&world);
+        assert_eq!(light.get(Vector3::new(1, 2, 1)), Light::DARK);
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let mut world = World::new();
+        let torch = torch(&mut world);
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(20, 1, 0));
+        world.set_block(Vector3::new(0, 1, 0), torch);
+        let light = LightMap::new(&world);
+        for x in 0..=14 {
+            let expected = 14 - x as u8;
+            assert_eq!(light.get(Vector3::new(x, 1, 0)), Light::new(0, expected));
+        }
+        assert_eq!(light.get(Vector3::new(20, 1, 0)), Light::DARK);
+        // Doesn't go through the walls
+        assert_eq!(light.get(Vector3::new(0, 3, 0)).block(), 0);
+    }
+
+    #[test]
+    fn opaque_blocks_can_glow() {
+        let mut world = World::new();
+        let glowstone = world.blocks.register(BlockType {
+            light: 15,
+            ..BlockType::solid("glowstone")
+        });
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(4, 1, 0));
+        world.set_block(Vector3::new(0, 1, 0), glowstone);
+        let light = LightMap::new(&world);
+        assert_eq!(light.get(Vector3::new(1, 1, 0)).block(), 14);
+        assert_eq!(light.get(Vector3::new(4, 1, 0)).block(), 11);
+    }
+
+    #[test]
+    fn light_crosses_chunk_boundaries() {
+        let mut world = World::new();
+        let torch = torch(&mut world);
+        let edge = CHUNK_SIZE - 1;
+        room(
+            &mut world,
+            Vector3::new(edge - 2, 1, 0),
+            Vector3::new(edge + 3, 1, 0),
+        );
+        world.light = LightMap::new(&world);
+        world.place_block(Vector3::new(edge, 1, 0), torch);
+        assert_eq!(world.light.get(Vector3::new(edge + 1, 1, 0)).block(), 13);
+        assert_eq!(world.light.get(Vector3::new(edge + 3, 1, 0)).block(), 11);
+        assert_matches_full_relight(&world, Vector3::new(edge, 1, 0), 5);
+    }
+
+    #[test]
+    fn placing_a_block_shades_below_it() {
+        let mut world = World::new();
+        floor(&mut world, 6);
+        world.light = LightMap::new(&world);
+
+        let pos = Vector3::new(0, 5, 0);
+        world.place_block(pos, DIRT);
+        assert_eq!(world.light.get(pos), Light::DARK);
+        assert_eq!(world.light.get(Vector3::new(0, 1, 0)).sky(), 14);
+        assert_matches_full_relight(&world, pos, 6);
+
+        world.place_block(pos, AIR);
+        assert_eq!(world.light.get(Vector3::new(0, 1, 0)), Light::SKY);
+        assert_matches_full_relight(&world, pos, 6);
+    }
+
+    #[test]
+    fn removing_a_torch_darkens_around_it() {
+        let mut world = World::new();
+        let torch = torch(&mut world);
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(6, 3, 6));
+        world.light = LightMap::new(&world);
+
+        let pos = Vector3::new(3, 2, 3);
+        world.place_block(pos, torch);
+        assert_eq!(world.light.get(Vector3::new(0, 1, 0)).block(), 7);
+        world.place_block(Vector3::new(0, 2, 0), torch);
+        world.place_block(pos, AIR);
+        // The other torch still lights the room
+        assert_eq!(world.light.get(pos).block(), 8);
+        assert_matches_full_relight(&world, pos, 5);
+
+        world.place_block(Vector3::new(0, 2, 0), AIR);
+        assert_eq!(world.light.get(pos), Light::DARK);
+    }
+
+    #[test]
+    fn opening_a_room_lets_sunlight_in() {
+        let mut world = World::new();
+        room(&mut world, Vector3::new(0, 1, 0), Vector3::new(4, 4, 4));
+        world.light = LightMap::new(&world);
+
+        let hole = Vector3::new(2, 5, 2);
+        world.place_block(hole, AIR);
+        assert_eq!(world.light.get(Vector3::new(2, 1, 2)).sky(), MAX_LIGHT);
+        assert_eq!(world.light.get(Vector3::new(0, 1, 0)).sky(), 11);
+        assert_matches_full_relight(&world, hole, 6);
+
+        world.place_block(hole, DIRT);
+        assert_eq!(world.light.get(Vector3::new(2, 1, 2)), Light::DARK);
+    }
+
+    #[test]
+    fn random_edits_match_a_full_relight() {
+        let mut world = World::new();
+        let torch = torch(&mut world);
+        floor(&mut world, 10);
+        world.light = LightMap::new(&world);
+
+        // Small linear congruential generator so the test is deterministic
+        let mut state = 12345u32;
+        let mut random = |range: i32| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as i32 % range
+        };
+        for _ in 0..300 {
+            let pos = Vector3::new(random(13) - 6, random(8) + 1, random(13) - 6);
+            let block = [AIR, DIRT, DIRT, torch][random(4) as usize];
+            world.place_block(pos, block);
+        }
+        assert_matches_full_relight(&world, Vector3::new(0, 8, 0), 12);
+    }
+}
@@ -0,0 +1,254 @@
+use cgmath::Vector3;
+
+use crate::{
+    block::{BlockId, BlockRegistry, AIR},
+    world::{local_pos, Chunk, World, CHUNK_SIZE, NEIGHBOURS},
+};
+
+// Level 0 is full resolution, every level after that halves it
+pub const LOD_LEVELS: usize = 4;
+
+// Level to draw a chunk at. Full resolution up to `lod_distance` blocks away,
+// then one level lower every time the distance doubles
+pub fn level(distance: f32, lod_distance: f32) -> usize {
+    if distance < lod_distance {
+        return 0;
+    }
+    // Saturates, a zero lod distance makes the log infinite
+    ((distance / lod_distance).log2() as usize)
+        .saturating_add(1)
+        .min(LOD_LEVELS - 1)
+}
+
+// A chunk downsampled to cells of 2^level blocks
+struct Cells {
+    size: i32,
+    blocks: Vec<BlockId>,
+}
+
+impl Cells {
+    fn get(&self, cell: Vector3<i32>) -> BlockId {
+        self.blocks[(cell.x + (cell.y + cell.z * self.size) * self.size) as usize]
+    }
+
+    // Each cell takes an opaque block from the eight below it if there is one,
+    // so terrain never gets thinner further away. The upper half is checked
+    // first to keep grass on top
+    fn downsample(&self, registry: &BlockRegistry) -> Self {
+        let size = self.size / 2;
+        let mut blocks = Vec::with_capacity((size * size * size) as usize);
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let mut children = [AIR; 8];
+                    for (i, child) in children.iter_mut().enumerate() {
+                        let i = i as i32;
+                        let offset = Vector3::new(i & 1, 1 - (i >> 2), i >> 1 & 1);
+                        *child = self.get(Vector3::new(x, y, z) * 2 + offset);
+                    }
+                    let block = children
+                        .iter()
+                        .find(|block| registry.get(**block).opaque)
+                        .or_else(|| children.iter().find(|block| **block != AIR))
+                        .copied()
+                        .unwrap_or(AIR);
+                    blocks.push(block);
+                }
+            }
+        }
+        Self { size, blocks }
+    }
+}
+
+// World positions and blocks of the cells at every level below full resolution
+// that have a face that can be seen, see `exposed_blocks` for level 0
+pub fn exposed_cells(
+    world: &World,
+    chunk_pos: Vector3<i32>,
+    chunk: &Chunk,
+) -> [Vec<(Vector3<i32>, BlockId)>; LOD_LEVELS - 1] {
+    let mut exposed: [Vec<(Vector3<i32>, BlockId)>; LOD_LEVELS - 1] = Default::default();
+    if let Chunk::Uniform(AIR) = chunk {
+        return exposed;
+    }
+
+    let origin = chunk_pos * CHUNK_SIZE;
+    let neighbours = NEIGHBOURS.map(|direction| world.chunk(chunk_pos + direction));
+    let uniform = match chunk {
+        Chunk::Uniform(block) => Some(*block),
+        Chunk::Dense(_) => None,
+    };
+    // Faces between two blocks of the same kind, like water next to water, are hidden
+    let shows_face = |block: BlockId, other: BlockId, pos: Vector3<i32>| {
+        other != block && !world.blocks.get(other).opaque && pos.y >= 0
+    };
+
+    // Buried chunks are common, skip them without building any cells
+    if let Some(block) = uniform {
+        let buried = neighbours
+            .iter()
+            .zip(NEIGHBOURS)
+            .all(|(neighbour, direction)| {
+                matches!(neighbour, Some(Chunk::Uniform(other))
+                if !shows_face(block, *other, origin + direction * CHUNK_SIZE))
+            });
+        if buried {
+            return exposed;
+        }
+    }
+
+    let mut cells = Cells {
+        size: CHUNK_SIZE,
+        blocks: match chunk {
+            Chunk::Uniform(_) => vec![],
+            Chunk::Dense(blocks) => blocks.to_vec(),
+        },
+    };
+    for (level, exposed) in exposed.iter_mut().enumerate() {
+        cells = match uniform {
+            Some(block) => {
+                let size = cells.size / 2;
+                Cells {
+                    size,
+                    blocks: vec![block; (size * size * size) as usize],
+                }
+            }
+            None => cells.downsample(&world.blocks),
+        };
+        let scale = 1 << (level + 1);
+        // Only the outer shell of a uniform chunk can border a different block
+        let on_shell = |c: i32| c == 0 || c == cells.size - 1;
+
+        // Cells on the edge of the chunk look at the blocks of the neighbouring
+        // chunk at full resolution, whatever level it's drawn at. That way the
+        // side of a chunk is drawn wherever a neighbour could be see-through,
+        // which hides any seams between levels
+        let boundary_shows_face = |block: BlockId, cell: Vector3<i32>, face: usize| {
+            let direction = NEIGHBOURS[face];
+            let start =
+                origin + cell * scale + direction.map(|c| if c > 0 { scale } else { c.min(0) });
+            match neighbours[face] {
+                None => shows_face(block, AIR, start),
+                Some(Chunk::Uniform(other)) => shows_face(block, *other, start),
+                Some(chunk) => (0..scale).any(|u| {
+                    (0..scale).any(|v| {
+                        let offset = match face / 2 {
+                            0 => Vector3::new(0, u, v),
+                            1 => Vector3::new(u, 0, v),
+                            _ => Vector3::new(u, v, 0),
+                        };
+                        let pos = start + offset;
+                        shows_face(block, chunk.get(local_pos(pos)), pos)
+                    })
+                }),
+            }
+        };
+
+        for z in 0..cells.size {
+            for y in 0..cells.size {
+                for x in 0..cells.size {
+                    let cell = Vector3::new(x, y, z);
+                    let block = cells.get(cell);
+                    if block == AIR
+                        || uniform.is_some() && !(on_shell(x) || on_shell(y) || on_shell(z))
+                    {
+                        continue;
+                    }
+                    let visible = NEIGHBOURS.iter().enumerate().any(|(face, direction)| {
+                        let next = cell + direction;
+                        if (0..cells.size).contains(&next.x)
+                            && (0..cells.size).contains(&next.y)
+                            && (0..cells.size).contains(&next.z)
+                        {
+                            shows_face(block, cells.get(next), origin + next * scale)
+                        } else {
+                            boundary_shows_face(block, cell, face)
+                        }
+                    });
+                    if visible {
+                        exposed.push((origin + cell * scale, block));
+                    }
+                }
+            }
+        }
+    }
+    exposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DIRT, GRASS, WATER};
+
+    #[test]
+    fn levels_halve_every_time_the_distance_doubles() {
+        for (distance, expected) in [
+            (0.0, 0),
+            (127.9, 0),
+            (128.0, 1),
+            (255.9, 1),
+            (256.0, 2),
+            (511.9, 2),
+            (512.0, 3),
+            (10000.0, LOD_LEVELS - 1),
+        ] {
+            assert_eq!(level(distance, 128.0), expected, "at {distance}");
+        }
+    }
+
+    #[test]
+    fn zero_lod_distance_is_the_lowest_level() {
+        assert_eq!(level(1.0, 0.0), LOD_LEVELS - 1);
+        assert_eq!(level(f32::MAX, 1.0), LOD_LEVELS - 1);
+    }
+
+    // Lays out the blocks of a 2x2x2 chunk as [y][z][x]
+    fn cube(layers: [[[BlockId; 2]; 2]; 2]) -> Cells {
+        let mut blocks = vec![AIR; 8];
+        for (y, layer) in layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, block) in row.iter().enumerate() {
+                    blocks[x + (y + z * 2) * 2] = *block;
+                }
+            }
+        }
+        Cells { size: 2, blocks }
+    }
+
+    fn downsampled(layers: [[[BlockId; 2]; 2]; 2]) -> BlockId {
+        let cells = cube(layers).downsample(&BlockRegistry::default());
+        assert_eq!(cells.size, 1);
+        cells.get(Vector3::new(0, 0, 0))
+    }
+
+    #[test]
+    fn downsampling_prefers_opaque_blocks_on_top() {
+        let dirt = [[DIRT, DIRT], [DIRT, DIRT]];
+        let empty = [[AIR, AIR], [AIR, AIR]];
+        assert_eq!(downsampled([empty, empty]), AIR);
+        assert_eq!(downsampled([dirt, empty]), DIRT);
+        // A single grass block on top of the dirt
+        assert_eq!(downsampled([dirt, [[AIR, AIR], [AIR, GRASS]]]), GRASS);
+        // Water above dirt is still solid from a distance
+        assert_eq!(downsampled([dirt, [[WATER, WATER], [WATER, WATER]]]), DIRT);
+        assert_eq!(downsampled([[[AIR, WATER], [AIR, AIR]], empty]), WATER);
+    }
+
+    #[test]
+    fn solid_chunks_only_show_their_shell() {
+        let mut world = World::new();
+        let chunk_pos = Vector3::new(0, 1, 0);
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    world.set_block(chunk_pos * CHUNK_SIZE + Vector3::new(x, y, z), DIRT);
+                }
+            }
+        }
+
+        let exposed = exposed_cells(&world, chunk_pos, world.chunk(chunk_pos).unwrap());
+        let counts = exposed.each_ref().map(Vec::len);
+        assert_eq!(counts, [8 * 8 * 8 - 6 * 6 * 6, 4 * 4 * 4 - 2 * 2 * 2, 8]);
+        assert!(exposed.iter().flatten().all(|(_, block)| *block == DIRT));
+    }
+}
@@ -1,5 +1,6 @@
-use voxel_engine::run;
+use voxel_engine::{config::Config, run};
 
-fn main() {
-    pollster::block_on(run());
+fn main() -> anyhow::Result<()> {
+    let config = Config::from_args()?;
+    pollster::block_on(run(config))
 }
@@ -0,0 +1,125 @@
+use cgmath::{Point3, Vector3};
+
+use crate::world::World;
+
+// Keeps boxes that are exactly touching a voxel from counting as overlapping it
+const EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // The space taken up by the voxel at `pos`
+    pub fn block(pos: Vector3<i32>) -> Self {
+        let min = Point3::new(pos.x as f32, pos.y as f32, pos.z as f32);
+        Self::new(min, min + Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translated(&self, offset: Vector3<f32>) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    // Smallest box containing both
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(
+            self.min.zip(other.min, f32::min),
+            self.max.zip(other.max, f32::max),
+        )
+    }
+}
+
+// Positions of all voxels the box overlaps
+pub fn voxels_in(aabb: &Aabb) -> impl Iterator<Item = Vector3<i32>> {
+    let lo = aabb.min.map(|c| (c + EPSILON).floor() as i32);
+    let hi = aabb.max.map(|c| (c - EPSILON).floor() as i32);
+    (lo.z..=hi.z).flat_map(move |z| {
+        (lo.y..=hi.y).flat_map(move |y| (lo.x..=hi.x).map(move |x| Vector3::new(x, y, z)))
+    })
+}
+
+// Moves the box by `delta` one axis at a time, vertical first, and returns how
+// far it actually got before hitting solid voxels. Every voxel between the start
+// and end position is checked, so fast movement can't tunnel through walls.
+pub fn sweep(world: &World, aabb: &Aabb, delta: Vector3<f32>) -> Vector3<f32> {
+    let mut aabb = *aabb;
+    let mut moved = Vector3::new(0.0, 0.0, 0.0);
+    for axis in [1, 0, 2] {
+        let d = clip_axis(world, &aabb, axis, delta[axis]);
+        let mut offset = Vector3::new(0.0, 0.0, 0.0);
+        offset[axis] = d;
+        aabb = aabb.translated(offset);
+        moved[axis] = d;
+    }
+    moved
+}
+
+fn clip_axis(world: &World, aabb: &Aabb, axis: usize, mut d: f32) -> f32 {
+    if d == 0.0 {
+        return 0.0;
+    }
+
+    // The region the box passes through on its way
+    let mut min = aabb.min;
+    let mut max = aabb.max;
+    if d > 0.0 {
+        max[axis] += d;
+    } else {
+        min[axis] += d;
+    }
+    for pos in voxels_in(&Aabb::new(min, max)) {
+        if !world.is_solid(pos) {
+            continue;
+        }
+        // Voxels the box is already stuck in are ignored, so it can always
+        // move out of them
+        let block = Aabb::block(pos);
+        if d > 0.0 && block.min[axis] >= aabb.max[axis] - EPSILON {
+            d = d.min(block.min[axis] - aabb.max[axis]);
+        } else if d < 0.0 && block.max[axis] <= aabb.min[axis] + EPSILON {
+            d = d.max(block.max[axis] - aabb.min[axis]);
+        }
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DIRT;
+
+    fn unit_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+    }
+
+    #[test]
+    fn touching_voxels_dont_overlap() {
+        let voxels: Vec<_> = voxels_in(&unit_box(0.0, 0.0, 0.0)).collect();
+        assert_eq!(voxels, [Vector3::new(0, 0, 0)]);
+        assert_eq!(voxels_in(&unit_box(0.5, 0.0, 0.0)).count(), 2);
+    }
+
+    #[test]
+    fn sweep_stops_at_solid_voxels() {
+        let mut world = World::new();
+        world.set_block(Vector3::new(5, 0, 0), DIRT);
+        let aabb = unit_box(0.0, 0.0, 0.0);
+
+        // Far enough in one step to skip over the voxel entirely
+        let moved = sweep(&world, &aabb, Vector3::new(10.0, 0.0, 0.0));
+        assert_eq!(moved, Vector3::new(4.0, 0.0, 0.0));
+        // Only the blocked axis is clipped
+        let moved = sweep(&world, &aabb, Vector3::new(10.0, 0.0, 2.0));
+        assert_eq!(moved, Vector3::new(4.0, 0.0, 2.0));
+        assert_eq!(
+            sweep(&world, &aabb, Vector3::new(-10.0, 0.0, 0.0)),
+            Vector3::new(-10.0, 0.0, 0.0)
+        );
+    }
+}
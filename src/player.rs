@@ -0,0 +1,481 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+
+use crate::{
+    physics::{sweep, voxels_in, Aabb},
+    world::World,
+};
+
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+const CROUCH_EYE_HEIGHT: f32 = 1.27;
+
+// Blocks per second (squared)
+const WALK_SPEED: f32 = 4.3;
+const GRAVITY: f32 = 32.0;
+const JUMP_SPEED: f32 = 9.0;
+const TERMINAL_VELOCITY: f32 = 78.0;
+// High enough to walk onto a single block without jumping
+const STEP_HEIGHT: f32 = 1.0;
+
+const SPRINT_FACTOR: f32 = 1.3;
+const CROUCH_FACTOR: f32 = 0.3;
+// How far the ground may drop away before a crouching player refuses to step off
+const EDGE_DROP: f32 = 0.5;
+const EDGE_STEP: f32 = 0.05;
+
+const CLIMB_SPEED: f32 = 2.35;
+
+// In water gravity is mostly cancelled out by buoyancy, and drag slows
+// everything down
+const WATER_GRAVITY: f32 = 4.0;
+const SWIM_ACCELERATION: f32 = 20.0;
+const WATER_DRAG: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveMode {
+    Walk,
+    // Free flying camera that passes through terrain
+    Fly,
+}
+
+// What the player is doing while walking, depends on the blocks around them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveState {
+    Walking,
+    Sprinting,
+    Crouching,
+    Swimming,
+    Climbing,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Controls {
+    // Horizontal direction the player wants to walk in, its length is ignored
+    pub wish: Vector3<f32>,
+    pub jump: bool,
+    pub sprint: bool,
+    pub crouch: bool,
+}
+
+// Properties of the blocks the player is in, see `BlockType`
+struct Surroundings {
+    liquid: bool,
+    climbable: bool,
+    speed_factor: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Player {
+    // Center of the feet
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    pub mode: MoveMode,
+    pub state: MoveState,
+}
+
+impl Player {
+    pub fn new(position: Point3<f32>, mode: MoveMode) -> Self {
+        Self {
+            position,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            on_ground: false,
+            mode,
+            state: MoveState::Walking,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = PLAYER_WIDTH / 2.0;
+        Aabb::new(
+            self.position + Vector3::new(-half, 0.0, -half),
+            self.position + Vector3::new(half, PLAYER_HEIGHT, half),
+        )
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        let height = match self.state {
+            MoveState::Crouching => CROUCH_EYE_HEIGHT,
+            _ => EYE_HEIGHT,
+        };
+        self.position + Vector3::new(0.0, height, 0.0)
+    }
+
+    pub fn set_eye(&mut self, eye: Point3<f32>) {
+        self.position = eye - Vector3::new(0.0, EYE_HEIGHT, 0.0);
+    }
+
+    pub fn toggle_fly(&mut self) {
+        self.mode = match self.mode {
+            MoveMode::Walk => MoveMode::Fly,
+            MoveMode::Fly => MoveMode::Walk,
+        };
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        self.on_ground = false;
+        self.state = MoveState::Walking;
+    }
+
+    pub fn update(&mut self, world: &World, controls: &Controls, dt: f32) {
+        let surroundings = self.surroundings(world);
+        let wish = Vector3::new(controls.wish.x, 0.0, controls.wish.z);
+        let moving = wish.magnitude2() > 0.0;
+
+        self.state = if surroundings.liquid {
+            MoveState::Swimming
+        } else if surroundings.climbable {
+            MoveState::Climbing
+        } else if controls.crouch {
+            MoveState::Crouching
+        } else if controls.sprint && moving {
+            MoveState::Sprinting
+        } else {
+            MoveState::Walking
+        };
+
+        let mut speed = WALK_SPEED * surroundings.speed_factor;
+        match self.state {
+            MoveState::Sprinting => speed *= SPRINT_FACTOR,
+            MoveState::Crouching => speed *= CROUCH_FACTOR,
+            _ => {}
+        }
+        let horizontal = if moving {
+            wish.normalize() * speed
+        } else {
+            wish
+        };
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+
+        match self.state {
+            MoveState::Swimming => {
+                self.velocity.y -= WATER_GRAVITY * dt;
+                if controls.jump {
+                    self.velocity.y += SWIM_ACCELERATION * dt;
+                }
+                self.velocity.y *= (1.0 - WATER_DRAG * dt).max(0.0);
+            }
+            MoveState::Climbing => {
+                self.velocity.y = if controls.jump || moving {
+                    CLIMB_SPEED
+                } else if controls.crouch {
+                    0.0
+                } else {
+                    -CLIMB_SPEED
+                };
+            }
+            _ => {
+                if controls.jump && self.on_ground {
+                    self.velocity.y = JUMP_SPEED;
+                }
+                self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+            }
+        }
+
+        let mut delta = self.velocity * dt;
+        if self.state == MoveState::Crouching && self.on_ground {
+            delta = self.guard_edges(world, delta);
+        }
+        self.move_and_collide(world, delta);
+    }
+
+    fn surroundings(&self, world: &World) -> Surroundings {
+        let mut surroundings = Surroundings {
+            liquid: false,
+            climbable: false,
+            speed_factor: 1.0,
+        };
+        for pos in voxels_in(&self.aabb()) {
+            let block = world.block_type(pos);
+            surroundings.liquid |= block.liquid;
+            surroundings.climbable |= block.climbable;
+            surroundings.speed_factor = surroundings.speed_factor.min(block.speed_factor);
+        }
+
+        // Soft ground slows you down too
+        if self.on_ground {
+            let below = self.position.map(|c| c.floor() as i32) - Vector3::unit_y();
+            let factor = world.block_type(below.to_vec()).speed_factor;
+            surroundings.speed_factor = surroundings.speed_factor.min(factor);
+        }
+        surroundings
+    }
+
+    // Shortens horizontal movement until there's still ground below the player,
+    // so crouching never walks off an edge
+    fn guard_edges(&self, world: &World, mut delta: Vector3<f32>) -> Vector3<f32> {
+        let aabb = self.aabb();
+        let supported = |dx: f32, dz: f32| {
+            let moved = aabb.translated(Vector3::new(dx, 0.0, dz));
+            sweep(world, &moved, Vector3::new(0.0, -EDGE_DROP, 0.0)).y > -EDGE_DROP
+        };
+        let shorten = |d: f32| {
+            if d.abs() <= EDGE_STEP {
+                0.0
+            } else {
+                d - EDGE_STEP * d.signum()
+            }
+        };
+
+        while delta.x != 0.0 && !supported(delta.x, 0.0) {
+            delta.x = shorten(delta.x);
+        }
+        while delta.z != 0.0 && !supported(0.0, delta.z) {
+            delta.z = shorten(delta.z);
+        }
+        while delta.x != 0.0 && delta.z != 0.0 && !supported(delta.x, delta.z) {
+            delta.x = shorten(delta.x);
+            delta.z = shorten(delta.z);
+        }
+        delta
+    }
+
+    pub fn move_and_collide(&mut self, world: &World, delta: Vector3<f32>) {
+        let aabb = self.aabb();
+        let mut moved = sweep(world, &aabb, delta);
+        let mut landed = delta.y < 0.0 && moved.y > delta.y;
+
+        // Walking into a wall while on the ground, see if we can climb on top of it
+        let blocked = moved.x != delta.x || moved.z != delta.z;
+        if self.on_ground && blocked {
+            let up = sweep(world, &aabb, Vector3::new(0.0, STEP_HEIGHT, 0.0));
+            let raised = aabb.translated(up);
+            let across = sweep(world, &raised, Vector3::new(delta.x, 0.0, delta.z));
+            let drop = -up.y + delta.y.min(0.0);
+            let down = sweep(
+                world,
+                &raised.translated(across),
+                Vector3::new(0.0, drop, 0.0),
+            );
+
+            let horizontal = |v: Vector3<f32>| v.x * v.x + v.z * v.z;
+            if horizontal(across) > horizontal(moved) {
+                moved = up + across + down;
+                landed = down.y > drop;
+            }
+        }
+
+        if moved.y != delta.y {
+            self.velocity.y = 0.0;
+        }
+        self.on_ground = landed;
+        self.position += moved;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockId, DIRT, WATER};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    // A flat floor whose top is at y = 1
+    fn floor() -> World {
+        let mut world = World::new();
+        for z in -8..=8 {
+            for x in -8..=8 {
+                world.set_block(Vector3::new(x, 0, z), DIRT);
+            }
+        }
+        world
+    }
+
+    fn controls(wish: Vector3<f32>) -> Controls {
+        Controls {
+            wish,
+            jump: false,
+            sprint: false,
+            crouch: false,
+        }
+    }
+
+    fn run(player: &mut Player, world: &World, controls: &Controls, seconds: f32) {
+        for _ in 0..(seconds / DT).round() as u32 {
+            player.update(world, controls, DT);
+        }
+    }
+
+    fn standing(x: f32, z: f32) -> Player {
+        Player::new(Point3::new(x, 1.0, z), MoveMode::Walk)
+    }
+
+    fn block(world: &World, name: &str) -> BlockId {
+        world.blocks.iter().position(|b| b.name == name).unwrap() as BlockId
+    }
+
+    // Fills everything above the floor up to y = `top` in a 5x5 area
+    fn fill(world: &mut World, block: BlockId, top: i32) {
+        for z in -2..=2 {
+            for y in 1..=top {
+                for x in -2..=2 {
+                    world.set_block(Vector3::new(x, y, z), block);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lands_on_the_floor() {
+        let world = floor();
+        let mut player = Player::new(Point3::new(0.5, 5.0, 0.5), MoveMode::Walk);
+        run(
+            &mut player,
+            &world,
+            &controls(Vector3::new(0.0, 0.0, 0.0)),
+            1.0,
+        );
+        assert_eq!(player.position.y, 1.0);
+        assert!(player.on_ground);
+        assert_eq!(player.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut world = floor();
+        for z in -8..=8 {
+            for y in 1..=2 {
+                world.set_block(Vector3::new(2, y, z), DIRT);
+            }
+        }
+        let mut player = standing(0.5, 0.5);
+        run(
+            &mut player,
+            &world,
+            &controls(Vector3::new(1.0, 0.0, 1.0)),
+            1.0,
+        );
+        // Stopped by the wall but kept going along it
+        assert!((player.position.x - (2.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-3);
+        assert!(player.position.z > 2.5);
+        assert_eq!(player.position.y, 1.0);
+    }
+
+    #[test]
+    fn doesnt_tunnel_at_high_speed() {
+        let mut world = floor();
+        for z in -8..=8 {
+            for y in 1..=2 {
+                world.set_block(Vector3::new(3, y, z), DIRT);
+            }
+        }
+
+        // Falling several blocks per tick onto a floor one block thick
+        let mut player = Player::new(Point3::new(0.5, 60.0, 0.5), MoveMode::Walk);
+        player.velocity.y = -TERMINAL_VELOCITY;
+        for _ in 0..20 {
+            player.update(&world, &controls(Vector3::new(0.0, 0.0, 0.0)), 0.1);
+        }
+        assert_eq!(player.position.y, 1.0);
+
+        // And a tick long enough to walk right through the wall
+        player.update(&world, &controls(Vector3::new(1.0, 0.0, 0.0)), 1.0);
+        assert!(player.position.x < 3.0 - PLAYER_WIDTH / 2.0 + 1e-3);
+    }
+
+    #[test]
+    fn steps_up_onto_a_single_block() {
+        let mut world = floor();
+        for z in -8..=8 {
+            world.set_block(Vector3::new(2, 1, z), DIRT);
+        }
+        let mut player = standing(0.5, 0.5);
+        run(
+            &mut player,
+            &world,
+            &controls(Vector3::new(1.0, 0.0, 0.0)),
+            0.5,
+        );
+        assert_eq!(player.position.y, 2.0);
+        assert!(player.position.x > 2.0);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn floats_in_water() {
+        let mut world = floor();
+        fill(&mut world, WATER, 10);
+        let mut player = Player::new(Point3::new(0.5, 5.0, 0.5), MoveMode::Walk);
+        let still = controls(Vector3::new(0.0, 0.0, 0.0));
+
+        // Sinks, but much slower than falling through the air would be
+        run(&mut player, &world, &still, 0.5);
+        assert_eq!(player.state, MoveState::Swimming);
+        assert!(player.position.y < 5.0);
+        assert!(player.position.y > 4.5);
+
+        let swim = Controls {
+            jump: true,
+            ..still
+        };
+        let y = player.position.y;
+        run(&mut player, &world, &swim, 0.5);
+        assert!(player.position.y > y);
+    }
+
+    #[test]
+    fn climbs_ladders() {
+        let mut world = floor();
+        let ladder = block(&world, "ladder");
+        fill(&mut world, ladder, 6);
+        let mut player = standing(0.5, 0.5);
+        let climb = Controls {
+            jump: true,
+            ..controls(Vector3::new(0.0, 0.0, 0.0))
+        };
+        run(&mut player, &world, &climb, 1.0);
+        assert_eq!(player.state, MoveState::Climbing);
+        assert!((player.position.y - (1.0 + CLIMB_SPEED)).abs() < 0.1);
+
+        // Crouching holds on
+        let hold = Controls {
+            crouch: true,
+            ..controls(Vector3::new(0.0, 0.0, 0.0))
+        };
+        let y = player.position.y;
+        run(&mut player, &world, &hold, 0.5);
+        assert_eq!(player.position.y, y);
+    }
+
+    #[test]
+    fn cobwebs_slow_you_down() {
+        let mut world = floor();
+        let cobweb = block(&world, "cobweb");
+        let walk = controls(Vector3::new(1.0, 0.0, 0.0));
+
+        let mut free = standing(-2.0, 0.5);
+        run(&mut free, &world, &walk, 0.5);
+
+        fill(&mut world, cobweb, 2);
+        let mut stuck = standing(-2.0, 0.5);
+        run(&mut stuck, &world, &walk, 0.5);
+
+        let free = free.position.x + 2.0;
+        let stuck = stuck.position.x + 2.0;
+        assert!((stuck / free - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn crouching_doesnt_walk_off_edges() {
+        // The floor ends at x = 9
+        let world = floor();
+        let walk = controls(Vector3::new(1.0, 0.0, 0.0));
+        let crouch = Controls {
+            crouch: true,
+            ..walk
+        };
+
+        let mut player = standing(8.0, 0.5);
+        run(&mut player, &world, &crouch, 2.0);
+        assert!(player.on_ground);
+        assert_eq!(player.position.y, 1.0);
+        // Some of the player may hang over the edge
+        assert!(player.position.x > 9.0);
+        assert!(player.position.x < 9.0 + PLAYER_WIDTH / 2.0);
+
+        let mut player = standing(8.0, 0.5);
+        run(&mut player, &world, &walk, 2.0);
+        // Drops down onto the solid bottom of the world
+        assert_eq!(player.position.y, 0.0);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::texture::Texture;
+
+// Render scales the keys step through, the config can't go outside them either
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+pub const RENDER_SCALE_STEP: f32 = 0.25;
+
+// What the world is drawn into before it reaches the window. With MSAA it's
+// drawn into a multisampled texture that's resolved at the end, and at a
+// render scale other than 1 it's resolved into a texture of that size, which
+// is then scaled to fill the window
+pub struct RenderTarget {
+    format: wgpu::TextureFormat,
+    // Supported by the adapter, from fewest to most
+    sample_counts: Vec<u32>,
+    sample_count: u32,
+    scale: f32,
+    size: (u32, u32),
+    pub depth: Texture,
+    // None without MSAA
+    multisampled: Option<wgpu::TextureView>,
+    // The resolved image and its bind group for the upscale, None when it's
+    // drawn straight into the window
+    scaled: Option<(wgpu::TextureView, wgpu::BindGroup)>,
+    sampler: wgpu::Sampler,
+    upscale_layout: wgpu::BindGroupLayout,
+    upscale_pipeline: wgpu::RenderPipeline,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        sample_counts: Vec<u32>,
+        sample_count: u32,
+        scale: f32,
+    ) -> Self {
+        let upscale_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("upscale_layout"),
+        });
+        // Linear filtering blurs the pixels together when scaling up, and
+        // averages them when scaling down
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("upscale.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Upscale Pipeline Layout"),
+            bind_group_layouts: &[&upscale_layout],
+            push_constant_ranges: &[],
+        });
+        let upscale_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Upscale Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let sample_count = closest_sample_count(&sample_counts, sample_count);
+        let size = (surface_config.width, surface_config.height);
+        let mut target = Self {
+            format: surface_config.format,
+            sample_counts,
+            sample_count,
+            scale: scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE),
+            size,
+            depth: Texture::create_depth_texture(device, size, sample_count, "depth_texture"),
+            multisampled: None,
+            scaled: None,
+            sampler,
+            upscale_layout,
+            upscale_pipeline,
+        };
+        target.resize(device, surface_config);
+        target
+    }
+
+    // Recreates the textures to fit the window, the render scale and the
+    // sample count
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        self.size = scaled_size(
+            (surface_config.width, surface_config.height),
+            self.scale,
+            device.limits().max_texture_dimension_2d,
+        );
+        self.depth =
+            Texture::create_depth_texture(device, self.size, self.sample_count, "depth_texture");
+
+        let colour_texture = |label, sample_count, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: self.size.0,
+                        height: self.size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        self.multisampled = (self.sample_count > 1).then(|| {
+            colour_texture(
+                "multisampled_colour",
+                self.sample_count,
+                wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        });
+        self.scaled = (self.size != (surface_config.width, surface_config.height)).then(|| {
+            let view = colour_texture(
+                "scaled_colour",
+                1,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.upscale_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+                label: Some("upscale_bind_group"),
+            });
+            (view, bind_group)
+        });
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Falls back to the most samples the adapter supports below the ones
+    // asked for. The pipelines drawing into the target have to be rebuilt
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) {
+        self.sample_count = closest_sample_count(&self.sample_counts, sample_count);
+        self.resize(device, surface_config);
+    }
+
+    // The next sample count the adapter supports, back to 1 after the most
+    pub fn next_sample_count(&self) -> u32 {
+        next_sample_count(&self.sample_counts, self.sample_count)
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(
+        &mut self,
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        scale: f32,
+    ) {
+        self.scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        self.resize(device, surface_config);
+    }
+
+    // Size of the textures in pixels
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    // Where the fragment shader's colour goes. Only the last pass has to
+    // resolve the samples
+    pub fn colour_attachment<'a>(
+        &'a self,
+        window: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        resolve: bool,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        let resolved = self.scaled.as_ref().map_or(window, |(view, _)| view);
+        let (view, resolve_target) = match &self.multisampled {
+            Some(multisampled) => (multisampled, resolve.then_some(resolved)),
+            None => (resolved, None),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        }
+    }
+
+    // Scales the image to fill the window, if it's not already drawn there
+    pub fn upscale(&self, encoder: &mut wgpu::CommandEncoder, window: &wgpu::TextureView) {
+        let Some((_, bind_group)) = &self.scaled else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Upscale Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: window,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.upscale_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// The most samples per pixel up to `wanted` that are supported
+fn closest_sample_count(supported: &[u32], wanted: u32) -> u32 {
+    let closest = supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= wanted)
+        .max()
+        .unwrap_or(1);
+    if closest != wanted {
+        log::warn!(
+            "{}x MSAA isn't supported, using {}x instead",
+            wanted,
+            closest
+        );
+    }
+    closest
+}
+
+fn next_sample_count(supported: &[u32], current: u32) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .find(|&count| count > current)
+        .unwrap_or(1)
+}
+
+// The window size at the render scale, never empty or too big for a texture
+fn scaled_size((width, height): (u32, u32), scale: f32, max: u32) -> (u32, u32) {
+    let scale = |side: u32| ((side as f32 * scale).round() as u32).clamp(1, max);
+    (scale(width), scale(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_sample_counts_fall_back() {
+        let supported = [1, 4];
+        assert_eq!(closest_sample_count(&supported, 4), 4);
+        assert_eq!(closest_sample_count(&supported, 8), 4);
+        assert_eq!(closest_sample_count(&supported, 2), 1);
+        assert_eq!(closest_sample_count(&supported, 0), 1);
+    }
+
+    #[test]
+    fn sample_counts_cycle_back_to_one() {
+        let supported = [1, 2, 4, 8];
+        assert_eq!(next_sample_count(&supported, 1), 2);
+        assert_eq!(next_sample_count(&supported, 4), 8);
+        assert_eq!(next_sample_count(&supported, 8), 1);
+        assert_eq!(next_sample_count(&[1], 1), 1);
+    }
+
+    #[test]
+    fn scaled_size_stays_in_bounds() {
+        assert_eq!(scaled_size((1000, 600), 1.0, 8192), (1000, 600));
+        assert_eq!(scaled_size((1000, 600), 0.5, 8192), (500, 300));
+        assert_eq!(scaled_size((1000, 600), 1.5, 8192), (1500, 900));
+        assert_eq!(scaled_size((5000, 1), 2.0, 8192), (8192, 2));
+        assert_eq!(scaled_size((3, 1), 0.25, 8192), (1, 1));
+    }
+}
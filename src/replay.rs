@@ -0,0 +1,177 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, input::InputEvent, simulation::Simulation};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    // Simulation time at the start of the frame
+    pub time: Duration,
+    pub dt: Duration,
+    // Events that arrived before this frame was simulated
+    pub events: Vec<InputEvent>,
+}
+
+// The config is stored as well, since the bindings, seed and starting camera
+// all influence what the same input stream does
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub config: Config,
+    pub frames: Vec<Frame>,
+    #[serde(skip)]
+    pending: Vec<InputEvent>,
+}
+
+impl Recording {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            frames: vec![],
+            pending: vec![],
+        }
+    }
+
+    pub fn push_event(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    pub fn end_frame(&mut self, time: Duration, dt: Duration) {
+        let events = std::mem::take(&mut self.pending);
+        self.frames.push(Frame { time, dt, events });
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read recording {}", path.display()))?;
+        let recording: Self = ron::from_str(&text)
+            .with_context(|| format!("failed to parse recording {}", path.display()))?;
+        recording
+            .config
+            .validate()
+            .with_context(|| format!("invalid config in recording {}", path.display()))?;
+        Ok(recording)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)
+            .with_context(|| format!("failed to write recording {}", path.display()))
+    }
+}
+
+pub struct Replay {
+    frames: std::vec::IntoIter<Frame>,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            frames: recording.frames.into_iter(),
+        }
+    }
+
+    // Feeds the events of the next recorded frame and returns the dt it should
+    // be simulated with, or None once the recording has ended
+    pub fn next_frame(&mut self, sim: &mut Simulation) -> Option<Duration> {
+        let frame = self.frames.next()?;
+        for event in frame.events {
+            sim.process_event(event);
+        }
+        Some(frame.dt)
+    }
+}
+
+// Replays a recording without opening a window and returns the final state
+pub fn run_headless(recording: Recording) -> Simulation {
+    let mut sim = Simulation::new(&recording.config);
+    let mut replay = Replay::new(recording);
+    while let Some(dt) = replay.next_frame(&mut sim) {
+        sim.update(dt);
+    }
+    sim
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::Vector3;
+    use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+    use super::*;
+    use crate::{input::Binding, world::Chunk};
+
+    fn key(key: VirtualKeyCode, state: ElementState) -> InputEvent {
+        InputEvent::Button(Binding::Key(key), state)
+    }
+
+    // A small world keeps generating it fast
+    fn config() -> Config {
+        Config {
+            world_size: 64,
+            ..Config::default()
+        }
+    }
+
+    // Drives a simulation with live input and returns it along with what it recorded
+    fn record() -> (Simulation, Recording) {
+        use ElementState::*;
+
+        let mut sim = Simulation::new(&config());
+        sim.start_recording(&config());
+        let dt = Duration::from_secs_f64(1.0 / 60.0);
+        let frames = [
+            vec![key(VirtualKeyCode::W, Pressed)],
+            vec![InputEvent::Button(
+                Binding::Mouse(MouseButton::Left),
+                Pressed,
+            )],
+            vec![InputEvent::MouseMotion(12.0, -4.0)],
+            vec![
+                InputEvent::MouseMotion(-3.0, 7.5),
+                key(VirtualKeyCode::Space, Pressed),
+            ],
+            vec![],
+            vec![
+                key(VirtualKeyCode::Space, Released),
+                InputEvent::Scroll(1.0),
+            ],
+            vec![key(VirtualKeyCode::W, Released)],
+        ];
+        for events in frames {
+            for event in events {
+                sim.process_event(event);
+            }
+            sim.update(dt);
+        }
+        let recording = sim.take_recording().unwrap();
+        (sim, recording)
+    }
+
+    fn chunks(sim: &Simulation) -> HashMap<Vector3<i32>, Chunk> {
+        sim.world
+            .chunks()
+            .map(|(pos, chunk)| (*pos, chunk.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn replays_are_deterministic() {
+        let (live, recording) = record();
+        assert_eq!(recording.frames.len(), 7);
+
+        // Both replays end up exactly where the live run did
+        for _ in 0..2 {
+            let replayed = run_headless(recording.clone());
+            assert_eq!(replayed.camera, live.camera);
+            assert_eq!(replayed.time, live.time);
+            assert!(chunks(&replayed) == chunks(&live));
+        }
+        // The input actually did something
+        assert_ne!(live.camera, Simulation::new(&config()).camera);
+    }
+}
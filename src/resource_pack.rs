@@ -0,0 +1,393 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use serde::Deserialize;
+
+use crate::skybox::{SkyboxSource, FACE_NAMES};
+
+pub const MANIFEST_NAME: &str = "pack.ron";
+
+// Read from pack.ron at the root of every pack. The directories are relative
+// to the root, a pack that leaves one out doesn't provide those resources
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub name: String,
+    pub description: String,
+    // PNGs named after a block and optionally a face, see BlockTextures
+    pub textures: Option<String>,
+    // WGSL files that replace the built in shaders of the same name
+    pub shaders: Option<String>,
+    pub sounds: Option<String>,
+    // Either a directory with px.png, nx.png, py.png, ny.png, pz.png and nz.png,
+    // or a single equirectangular image
+    pub sky: Option<String>,
+}
+
+// Where the files of a pack come from. Zips are small enough to keep in memory
+#[derive(Debug)]
+enum Source {
+    Dir(PathBuf),
+    Zip(BTreeMap<String, Vec<u8>>),
+}
+
+#[derive(Debug)]
+pub struct ResourcePack {
+    pub manifest: Manifest,
+    // Shown in errors
+    location: String,
+    source: Source,
+}
+
+impl ResourcePack {
+    // Loads a directory, or a zip if the path is a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let location = path.display().to_string();
+        if path.is_dir() {
+            return Self::new(location, Source::Dir(path.to_path_buf()));
+        }
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open resource pack {}", location))?;
+        Self::from_zip(location, file)
+    }
+
+    pub fn from_zip<R: Read + Seek>(location: String, reader: R) -> Result<Self> {
+        let mut archive = zip::ZipArchive::new(reader)
+            .with_context(|| format!("{} isn't a directory or a zip", location))?;
+        let mut files = BTreeMap::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)
+                .with_context(|| format!("failed to read {} in {}", file.name(), location))?;
+            files.insert(file.name().to_string(), bytes);
+        }
+        Self::new(location, Source::Zip(files))
+    }
+
+    fn new(location: String, source: Source) -> Result<Self> {
+        let mut pack = Self {
+            manifest: Manifest::default(),
+            location,
+            source,
+        };
+        let manifest = pack
+            .read(MANIFEST_NAME)?
+            .with_context(|| format!("{} has no {}", pack.location, MANIFEST_NAME))?;
+        pack.manifest = std::str::from_utf8(&manifest)
+            .map_err(Error::from)
+            .and_then(|text| Ok(ron::from_str(text)?))
+            .with_context(|| format!("failed to parse {} in {}", MANIFEST_NAME, pack.location))?;
+        pack.validate()?;
+        Ok(pack)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.manifest.name.is_empty(),
+            "{} in {} needs a name",
+            MANIFEST_NAME,
+            self.location
+        );
+        let dirs = [
+            &self.manifest.textures,
+            &self.manifest.shaders,
+            &self.manifest.sounds,
+        ];
+        for dir in dirs.into_iter().flatten() {
+            ensure!(
+                self.has_dir(dir),
+                "resource pack {} lists a {} directory that doesn't exist",
+                self.manifest.name,
+                dir
+            );
+        }
+        if let Some(sky) = &self.manifest.sky {
+            ensure!(
+                self.has_dir(sky) || self.has_file(sky),
+                "resource pack {} lists a sky {} that doesn't exist",
+                self.manifest.name,
+                sky
+            );
+        }
+        Ok(())
+    }
+
+    fn has_dir(&self, dir: &str) -> bool {
+        match &self.source {
+            Source::Dir(root) => root.join(dir).is_dir(),
+            Source::Zip(files) => {
+                let prefix = format!("{}/", dir.trim_end_matches('/'));
+                files.keys().any(|name| name.starts_with(&prefix))
+            }
+        }
+    }
+
+    fn has_file(&self, path: &str) -> bool {
+        match &self.source {
+            Source::Dir(root) => root.join(path).is_file(),
+            Source::Zip(files) => files.contains_key(path),
+        }
+    }
+
+    // None if the pack doesn't have the file
+    fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match &self.source {
+            Source::Dir(root) => {
+                let file = root.join(path);
+                if !file.is_file() {
+                    return Ok(None);
+                }
+                let bytes = std::fs::read(&file)
+                    .with_context(|| format!("failed to read {}", file.display()))?;
+                Ok(Some(bytes))
+            }
+            Source::Zip(files) => Ok(files.get(path).cloned()),
+        }
+    }
+
+    // Names and contents of the files directly inside a directory of the pack
+    // that have the extension, sorted by name
+    fn files(&self, dir: &str, extension: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let dir = dir.trim_end_matches('/');
+        let mut names = match &self.source {
+            Source::Dir(root) => {
+                let path = root.join(dir);
+                std::fs::read_dir(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                    .collect::<Result<Vec<_>>>()?
+            }
+            Source::Zip(files) => files
+                .keys()
+                .filter_map(|name| name.strip_prefix(dir)?.strip_prefix('/'))
+                .filter(|name| !name.contains('/'))
+                .map(str::to_string)
+                .collect(),
+        };
+        names.retain(|name| {
+            Path::new(name)
+                .extension()
+                .is_some_and(|ext| ext == extension)
+        });
+        names.sort();
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                self.read(&format!("{}/{}", dir, name))
+                    .transpose()
+                    .map(|bytes| Ok((name, bytes?)))
+            })
+            .collect()
+    }
+
+    // Block textures as (file name, PNG bytes)
+    pub fn textures(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        match &self.manifest.textures {
+            Some(dir) => self.files(dir, "png"),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn shader(&self, name: &str) -> Result<Option<String>> {
+        let Some(dir) = &self.manifest.shaders else {
+            return Ok(None);
+        };
+        let Some(bytes) = self.read(&format!("{}/{}", dir.trim_end_matches('/'), name))? else {
+            return Ok(None);
+        };
+        let source = String::from_utf8(bytes)
+            .with_context(|| format!("{} in {} isn't valid UTF-8", name, self.manifest.name))?;
+        Ok(Some(source))
+    }
+
+    pub fn skybox(&self) -> Result<Option<SkyboxSource>> {
+        let Some(sky) = &self.manifest.sky else {
+            return Ok(None);
+        };
+        if let Some(bytes) = self.read(sky)? {
+            return Ok(Some(SkyboxSource::Equirectangular(bytes)));
+        }
+        let dir = sky.trim_end_matches('/');
+        let mut faces = Vec::with_capacity(6);
+        for name in FACE_NAMES {
+            let bytes = self
+                .read(&format!("{}/{}.png", dir, name))?
+                .with_context(|| {
+                    format!(
+                        "sky of resource pack {} has no {}.png",
+                        self.manifest.name, name
+                    )
+                })?;
+            faces.push(bytes);
+        }
+        Ok(Some(SkyboxSource::Faces(faces.try_into().unwrap())))
+    }
+}
+
+// Resource packs stacked on top of each other, later packs override the
+// resources of earlier ones with the same name
+#[derive(Debug)]
+pub struct ResourcePacks {
+    packs: Vec<ResourcePack>,
+}
+
+impl ResourcePacks {
+    pub fn new(packs: Vec<ResourcePack>) -> Self {
+        Self { packs }
+    }
+
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        let packs = paths
+            .iter()
+            .map(ResourcePack::load)
+            .collect::<Result<Vec<_>>>()?;
+        for pack in &packs {
+            log::info!("loaded resource pack {}", pack.manifest.name);
+        }
+        Ok(Self::new(packs))
+    }
+
+    // Lowest priority first
+    pub fn iter(&self) -> impl Iterator<Item = &ResourcePack> {
+        self.packs.iter()
+    }
+
+    // Source of the shader from the highest priority pack that has one
+    pub fn shader(&self, name: &str) -> Result<Option<String>> {
+        for pack in self.packs.iter().rev() {
+            if let Some(source) = pack.shader(name)? {
+                log::info!("using {} from resource pack {}", name, pack.manifest.name);
+                return Ok(Some(source));
+            }
+        }
+        Ok(None)
+    }
+
+    // Skybox from the highest priority pack that has one
+    pub fn skybox(&self) -> Result<Option<SkyboxSource>> {
+        for pack in self.packs.iter().rev() {
+            if let Some(skybox) = pack.skybox()? {
+                log::info!("using the sky from resource pack {}", pack.manifest.name);
+                return Ok(Some(skybox));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    // A zipped pack with the given files
+    pub fn zip_pack(files: &[(&str, &[u8])]) -> Result<ResourcePack> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, bytes) in files {
+            writer.start_file(*name, zip::write::FileOptions::default())?;
+            writer.write_all(bytes)?;
+        }
+        let zip = writer.finish()?;
+        ResourcePack::from_zip("test.zip".to_string(), Cursor::new(zip.into_inner()))
+    }
+
+    pub const TEXTURES_MANIFEST: &[u8] = b"(name: \"test\", textures: Some(\"blocks\"))";
+
+    #[test]
+    fn zip_pack_lists_its_textures() {
+        let pack = zip_pack(&[
+            ("pack.ron", TEXTURES_MANIFEST),
+            ("blocks/dirt.png", b"dirt"),
+            ("blocks/notes.txt", b"not a texture"),
+            ("blocks/old/stone.png", b"nested"),
+            ("other/stone.png", b"elsewhere"),
+        ])
+        .unwrap();
+        let textures = pack.textures().unwrap();
+        assert_eq!(textures, vec![("dirt.png".to_string(), b"dirt".to_vec())]);
+        assert_eq!(pack.shader("shader.wgsl").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_packs_are_rejected() {
+        // No manifest
+        assert!(zip_pack(&[("blocks/dirt.png", b"dirt")]).is_err());
+        assert!(zip_pack(&[("pack.ron", b"(name: \"test\"")]).is_err());
+        assert!(zip_pack(&[("pack.ron", b"(description: \"no name\")")]).is_err());
+        // Missing textures directory
+        assert!(zip_pack(&[("pack.ron", TEXTURES_MANIFEST)]).is_err());
+        assert!(ResourcePack::from_zip("bad.zip".to_string(), Cursor::new(b"nope")).is_err());
+    }
+
+    #[test]
+    fn later_packs_override_shaders() {
+        let pack = |source: &[u8]| {
+            zip_pack(&[
+                ("pack.ron", b"(name: \"test\", shaders: Some(\"shaders\"))"),
+                ("shaders/shader.wgsl", source),
+            ])
+            .unwrap()
+        };
+        let packs = ResourcePacks::new(vec![pack(b"first"), pack(b"second")]);
+        assert_eq!(
+            packs.shader("shader.wgsl").unwrap().as_deref(),
+            Some("second")
+        );
+        assert_eq!(packs.shader("cull.wgsl").unwrap(), None);
+    }
+
+    #[test]
+    fn skies_are_faces_or_one_image() {
+        let manifest = b"(name: \"test\", sky: Some(\"sky\"))";
+        let mut files: Vec<(String, &[u8])> = FACE_NAMES
+            .iter()
+            .map(|name| (format!("sky/{}.png", name), name.as_bytes()))
+            .collect();
+        files.push(("pack.ron".to_string(), manifest));
+        let borrowed = |files: &[(String, &[u8])]| {
+            zip_pack(
+                &files
+                    .iter()
+                    .map(|(n, b)| (n.as_str(), *b))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let faces = borrowed(&files).unwrap().skybox().unwrap();
+        let expected = FACE_NAMES.map(|name| name.as_bytes().to_vec());
+        assert_eq!(faces, Some(SkyboxSource::Faces(expected)));
+
+        // A missing face only shows up once the sky is read
+        files.remove(2);
+        assert!(borrowed(&files).unwrap().skybox().is_err());
+
+        let image = zip_pack(&[
+            ("pack.ron", b"(name: \"image\", sky: Some(\"sky.png\"))"),
+            ("sky.png", b"image"),
+        ])
+        .unwrap();
+        let packs = ResourcePacks::new(vec![borrowed(&files).unwrap(), image]);
+        assert_eq!(
+            packs.skybox().unwrap(),
+            Some(SkyboxSource::Equirectangular(b"image".to_vec()))
+        );
+        assert!(zip_pack(&[("pack.ron", manifest)]).is_err());
+        assert_eq!(
+            zip_pack(&[("pack.ron", TEXTURES_MANIFEST), ("blocks/a.png", b"")])
+                .unwrap()
+                .skybox()
+                .unwrap(),
+            None
+        );
+    }
+}
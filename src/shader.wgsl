@@ -3,11 +3,24 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) face: u32,
+    @location(3) normal: vec3<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+    @location(2) normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    // Distance in front of the camera, picks the shadow cascade
+    @location(4) view_depth: f32,
+    // 0 in a tight corner, 1 out in the open
+    @location(5) ao: f32,
+    // Brightness of the skylight and block light reaching the face
+    @location(6) light: vec2<f32>,
+    // See block::Material
+    @location(7) @interpolate(flat) material: u32,
 };
 
 struct InstanceInput {
@@ -15,53 +28,338 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    // Texture layer of every face, one byte each
+    @location(9) layers: vec2<u32>,
+    // Ambient occlusion of every face corner in two bits each, then a bit per
+    // face that says to split its quad along the other diagonal, a bit per
+    // face that hides it and the material in the top four bits
+    @location(10) ao: vec2<u32>,
+    // Light in front of every face, one byte each with the skylight level in
+    // the top four bits and the block light level in the bottom four
+    @location(11) light: vec2<u32>,
 };
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform; 
 
+// Seconds since the simulation started
 @group(2) @binding(0)
-var<uniform> count: f32; 
+var<uniform> time: f32;
+
+struct Water {
+    // What deep water fades to, shallow water is the colour of its texture
+    deep_colour: vec3<f32>,
+    // From trough to crest in blocks
+    wave_height: f32,
+    wave_speed: f32,
+    // Blocks of water it takes to hide most of what's behind it
+    clarity: f32,
+    // The camera's near and far planes
+    near: f32,
+    far: f32,
+};
+@group(2) @binding(1)
+var<uniform> water: Water;
+// Depth of everything drawn before the translucent blocks
+@group(2) @binding(2)
+var scene_depth: texture_depth_2d;
+
+const MATERIAL_WATER: u32 = 1u;
+
+// One sine wave travelling across the water, its height from -1 to 1 and its
+// slope along x and z
+fn wave(xz: vec2<f32>, direction: vec2<f32>, wavelength: f32) -> vec3<f32> {
+    let k = 6.2831853 / wavelength;
+    // Longer waves travel faster, like they do out at sea
+    let phase = k * (dot(xz, direction) - sqrt(wavelength) * time * water.wave_speed);
+    return vec3<f32>(sin(phase), direction * k * cos(phase));
+}
+
+// Height of the water surface below the top of the block, from -wave_height
+// to 0 so it never pokes into the block above, and its slope along x and z
+fn waves(xz: vec2<f32>) -> vec3<f32> {
+    let sum = wave(xz, vec2<f32>(0.8, 0.6), 9.0)
+        + wave(xz, vec2<f32>(-0.6, 0.8), 5.0) * 0.5
+        + wave(xz, vec2<f32>(0.2, -0.98), 3.0) * 0.25;
+    // The sum goes from -1.75 to 1.75
+    let scale = water.wave_height * 0.5 / 1.75;
+    return vec3<f32>(sum.x * scale - water.wave_height * 0.5, sum.yz * scale);
+}
+
+// Light levels go from 0 to 15, each one a bit dimmer than the one above
+fn brightness(level: u32) -> f32 {
+    return pow(0.8, f32(15u - level));
+}
 
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32,
 ) -> VertexOutput {
-    var model_matrix = mat4x4<f32>(
+    let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
-    if count != 0. {
-        let s = 0.01;
-        let new_y = (sin((model_matrix.w.x + count) * s) + sin((model_matrix.w.z + count) * s * 0.543));
-        model_matrix.w.y += new_y * 3.;
+    let material = instance.ao.y >> 28u;
+
+    // Every face has four vertices in order around it, so the index says which
+    // corner this is
+    var corner = vertex_index % 4u;
+    var position = model.position;
+    var tex_coords = model.tex_coords;
+    if (instance.ao.y & (1u << (16u + model.face))) != 0u {
+        // Moving every vertex on to the next corner splits the quad along the
+        // other diagonal. Corners go counter clockwise around the normal, so
+        // that's a quarter turn around the middle of the face
+        corner = (corner + 1u) % 4u;
+        let offset = position - vec3<f32>(0.0, 0.0, 1.0);
+        position = vec3<f32>(0.0, 0.0, 1.0) + model.normal * dot(model.normal, offset)
+            + cross(model.normal, offset);
+        // The texture turns with it, every face maps its corners the same way
+        tex_coords = vec2<f32>(tex_coords.y, 1.0 - tex_coords.x);
     }
-    
-    // model_matrix.w.y *= 50.;
+    let ao_index = model.face * 4u + corner;
+    let ao = (instance.ao[ao_index / 16u] >> (ao_index % 16u * 2u)) & 3u;
 
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    var clip_pos = camera.view_proj * model_matrix  * vec4<f32>(model.position, 1.0);
-    // clip_pos.y = sin(clip_pos.x * 0.1);
+    out.material = material;
+    out.tex_coords = tex_coords;
+    out.ao = f32(ao) / 3.0;
+    out.layer = (instance.layers[model.face / 4u] >> (model.face % 4u * 8u)) & 0xffu;
+    let light = (instance.light[model.face / 4u] >> (model.face % 4u * 8u)) & 0xffu;
+    out.light = vec2<f32>(brightness(light >> 4u), brightness(light & 15u));
+    // The scale is the same on every axis, so the model matrix keeps normals
+    // perpendicular
+    out.normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    var world_position = model_matrix * vec4<f32>(position, 1.0);
+    // Only the top of the water moves, the corners along the top of its sides
+    // move with it so there are no gaps. Under more water it stays flat
+    let top_hidden = (instance.ao.y & (1u << 24u)) != 0u;
+    if material == MATERIAL_WATER && position.y > 0.0 && !top_hidden {
+        world_position.y += waves(world_position.xz).x;
+    }
+    out.world_position = world_position.xyz;
+    let clip_pos = camera.view_proj * world_position;
+    out.view_depth = clip_pos.w;
     out.clip_position = clip_pos;
+    if (instance.ao.y & (1u << (22u + model.face))) != 0u {
+        // Every corner in the same spot leaves nothing to draw
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
     return out;
 }
 
 // Fragment shader
 
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: texture_2d_array<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
+struct Light {
+    // Towards the sun, normalized
+    direction: vec3<f32>,
+    colour: vec3<f32>,
+    ambient: vec3<f32>,
+};
+@group(3) @binding(0)
+var<uniform> light: Light;
+
+// Colour of the light given off by torches and other glowing blocks
+const BLOCK_LIGHT_COLOUR = vec3<f32>(1.0, 0.85, 0.6);
+
+struct Shadow {
+    view_proj: array<mat4x4<f32>, 4>,
+    // View depth each cascade ends at
+    splits: vec4<f32>,
+    // Size of a shadow map texel in blocks
+    texel_sizes: vec4<f32>,
+    cascade_count: u32,
+};
+@group(3) @binding(1)
+var<uniform> shadow: Shadow;
+@group(3) @binding(2)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+struct Fog {
+    // The sky at the horizon, so the terrain fades into it
+    colour: vec3<f32>,
+    // Distances from the camera in blocks, everything past the end is hidden
+    start: f32,
+    end: f32,
+    // 0 for off, 1 for linear and 2 for exponential
+    mode: u32,
+};
+@group(3) @binding(4)
+var<uniform> fog: Fog;
+
+// How much of a point is hidden by fog, from 0 to 1
+fn fog_amount(world_position: vec3<f32>) -> f32 {
+    let distance = length(world_position - camera.view_position.xyz);
+    let t = clamp((distance - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    switch fog.mode {
+        case 1u: {
+            return t;
+        }
+        case 2u: {
+            // Squared so it stays thin for a while, reaching 1 at the end
+            return (1.0 - exp(-9.0 * t * t)) / (1.0 - exp(-9.0));
+        }
+        default: {
+            return 0.0;
+        }
+    }
+}
+
+// How much of the sun reaches a point, from 0 in full shadow to 1
+fn sunlight(world_position: vec3<f32>, normal: vec3<f32>, view_depth: f32) -> f32 {
+    var cascade = 0u;
+    while cascade < shadow.cascade_count && view_depth >= shadow.splits[cascade] {
+        cascade += 1u;
+    }
+    if cascade >= shadow.cascade_count {
+        return 1.0;
+    }
+
+    // Moving the point off the face by about a texel keeps the face from
+    // shadowing itself
+    let offset = normal * shadow.texel_sizes[cascade] * 1.5;
+    let light_position = shadow.view_proj[cascade] * vec4<f32>(world_position + offset, 1.0);
+    let ndc = light_position.xyz / light_position.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    // Percentage closer filtering over 3x3 texels softens the edges
+    let texel = 1.0 / f32(textureDimensions(shadow_map).x);
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let sample_uv = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, sample_uv, cascade, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+fn shade(in: VertexOutput, colour: vec4<f32>) -> vec4<f32> {
+    let normal = normalize(in.normal);
+    var diffuse = max(dot(normal, light.direction), 0.0);
+    if diffuse > 0.0 {
+        diffuse *= sunlight(in.world_position, normal, in.view_depth);
+    }
+    let occlusion = mix(0.4, 1.0, in.ao);
+    let sky = (light.ambient + light.colour * diffuse) * in.light.x;
+    let lighting = (sky + BLOCK_LIGHT_COLOUR * in.light.y) * occlusion;
+    let lit = mix(colour.rgb * lighting, fog.colour, fog_amount(in.world_position));
+    return vec4<f32>(lit, colour.a);
+}
+
+fn hash(cell: vec2<i32>) -> f32 {
+    var h = (bitcast<u32>(cell.x) * 73856093u) ^ (bitcast<u32>(cell.y) * 19349663u);
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = h ^ (h >> 16u);
+    return f32(h) / 4294967295.0;
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = fract(p);
+    let t = f * f * (3.0 - 2.0 * f);
+    let a = hash(cell);
+    let b = hash(cell + vec2<i32>(1, 0));
+    let c = hash(cell + vec2<i32>(0, 1));
+    let d = hash(cell + vec2<i32>(1, 1));
+    return mix(mix(a, b, t.x), mix(c, d, t.x), t.y);
+}
+
+// Slope of some noise scrolling across the water
+fn ripple(xz: vec2<f32>, velocity: vec2<f32>, scale: f32) -> vec2<f32> {
+    let p = xz * scale + velocity * time * water.wave_speed;
+    let e = 0.05;
+    let dx = value_noise(p + vec2<f32>(e, 0.0)) - value_noise(p - vec2<f32>(e, 0.0));
+    let dz = value_noise(p + vec2<f32>(0.0, e)) - value_noise(p - vec2<f32>(0.0, e));
+    return vec2<f32>(dx, dz) / (2.0 * e);
+}
+
+// Normal of the water surface, the waves with two layers of small ripples
+// scrolling across them in different directions
+fn water_normal(xz: vec2<f32>) -> vec3<f32> {
+    let slope = waves(xz).yz
+        + ripple(xz, vec2<f32>(0.4, 0.3), 1.5) * 0.04
+        + ripple(xz, vec2<f32>(-0.3, 0.5), 3.7) * 0.02;
+    return normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+}
+
+// From a value in the depth texture back to a distance in front of the camera
+fn linear_depth(depth: f32) -> f32 {
+    return water.near * water.far / (water.far - depth * (water.far - water.near));
+}
+
+fn shade_water(in: VertexOutput, texel: vec4<f32>) -> vec4<f32> {
+    let face_normal = normalize(in.normal);
+    var surface = in;
+    if face_normal.y > 0.5 {
+        surface.normal = water_normal(in.world_position.xz);
+    }
+    let normal = normalize(surface.normal);
+    let to_eye = camera.view_position.xyz - in.world_position;
+    let view = normalize(to_eye);
+
+    // How much water the view goes through behind the surface before it hits
+    // something. From under the water that's in front of the surface instead,
+    // so it's left clear
+    var depth = 0.0;
+    if dot(face_normal, to_eye) > 0.0 {
+        let behind = linear_depth(textureLoad(scene_depth, vec2<i32>(in.clip_position.xy), 0));
+        // From a difference in view depth to a distance along the view
+        let thickness = max(behind - in.view_depth, 0.0) * length(to_eye) / in.view_depth;
+        depth = 1.0 - exp(-thickness / water.clarity);
+    }
+    // More of the sky is reflected when looking along the surface
+    let fresnel = pow(1.0 - abs(dot(normal, view)), 5.0);
+    let colour = mix(texel.rgb, water.deep_colour, depth);
+    let alpha = mix(mix(texel.a, 1.0, depth), 1.0, fresnel);
+    var shaded = shade(surface, vec4<f32>(colour, alpha));
+
+    // The sun glinting off the ripples, unless something's in the way
+    let halfway = normalize(light.direction + view);
+    var glint = pow(max(dot(normal, halfway), 0.0), 200.0) * in.light.x;
+    if glint > 0.0 {
+        glint *= sunlight(in.world_position, face_normal, in.view_depth);
+    }
+    let clear = 1.0 - fog_amount(in.world_position);
+    let reflected = mix(shaded.rgb, fog.colour, fresnel * 0.5 * clear);
+    return vec4<f32>(reflected + light.colour * glint * clear, shaded.a);
+}
+
+// Opaque and translucent blocks, translucent ones are blended by their alpha
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    //return vec4<f32>(in.color, 1.0);
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let colour = textureSample(t_diffuse, s_diffuse, in.tex_coords, in.layer);
+    if in.material == MATERIAL_WATER {
+        return shade_water(in, colour);
+    }
+    return shade(in, colour);
+}
+
+// Blocks with see through gaps, which are left out entirely
+@fragment
+fn fs_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(t_diffuse, s_diffuse, in.tex_coords, in.layer);
+    if colour.a < 0.5 {
+        discard;
+    }
+    return shade(in, vec4<f32>(colour.rgb, 1.0));
+}
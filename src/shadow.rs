@@ -0,0 +1,475 @@
+use std::ops::Range;
+
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4,
+};
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    block::Transparency,
+    camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX},
+    frustum::Frustum,
+    instance::{ChunkInstances, InstanceRaw},
+    texture::DEPTH_FORMAT,
+    vertices::Vertex,
+};
+
+pub const MAX_CASCADES: usize = 4;
+// How far towards the sun past a cascade terrain can still cast shadows into it
+const CASTER_DISTANCE: f32 = 512.0;
+// Matrices are bound with a dynamic offset, which has to be aligned to this
+const MATRIX_STRIDE: u64 = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    /// Number of shadow maps the view is split into, each covering a longer
+    /// distance than the one before at a lower resolution. 0 turns shadows
+    /// off, at most 4. Default: 3
+    pub cascades: u32,
+    /// Width and height of each shadow map in texels. Default: 2048
+    pub resolution: u32,
+    /// Distance in blocks shadows are drawn up to. Default: 300.0
+    pub distance: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            cascades: 3,
+            resolution: 2048,
+            distance: 300.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    // View depth each cascade ends at
+    splits: [f32; MAX_CASCADES],
+    // Size of a shadow map texel in blocks, per cascade
+    texel_sizes: [f32; MAX_CASCADES],
+    cascade_count: u32,
+    _padding: [u32; 3],
+}
+
+// Part of the view covered by one shadow map
+#[derive(Debug, Clone, Copy)]
+struct Cascade {
+    view_proj: Matrix4<f32>,
+    split: f32,
+    texel_size: f32,
+}
+
+// Splits the view between the near plane and `distance` into cascades and fits
+// an orthographic projection from the sun around each. Near cascades get
+// shorter pieces of the view, so they get more texels per block
+fn fit_cascades(
+    view: Matrix4<f32>,
+    projection: &Projection,
+    sun_direction: Vector3<f32>,
+    config: &ShadowConfig,
+    count: usize,
+) -> Vec<Cascade> {
+    let (znear, zfar) = projection.depth_range();
+    let far = config.distance.min(zfar);
+    // Blend of logarithmic and even splits
+    let split = |i: usize| {
+        let t = i as f32 / count as f32;
+        let log = znear * (far / znear).powf(t);
+        let even = znear + (far - znear) * t;
+        0.75 * log + 0.25 * even
+    };
+
+    let up = if sun_direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let light_view = Matrix4::look_to_rh(Point3::origin(), -sun_direction, up);
+
+    (0..count)
+        .map(|i| {
+            let (near, far) = (split(i), split(i + 1));
+            let to_world = (projection.calc_matrix_between(near, far) * view)
+                .invert()
+                .unwrap_or(Matrix4::identity());
+            let mut corners = vec![];
+            for x in [-1.0, 1.0] {
+                for y in [-1.0, 1.0] {
+                    for z in [0.0, 1.0] {
+                        let corner = to_world * Vector4::new(x, y, z, 1.0);
+                        corners.push(Point3::from_homogeneous(corner));
+                    }
+                }
+            }
+
+            // A sphere around the piece of the view keeps the same size however
+            // the camera turns, so shadows don't swim
+            let center = Point3::centroid(&corners);
+            let radius = corners
+                .iter()
+                .map(|corner| (corner - center).magnitude())
+                .fold(0.0, f32::max)
+                .ceil();
+            let texel_size = radius * 2.0 / config.resolution as f32;
+
+            // Moving the box a whole texel at a time stops edges from flickering
+            let center = light_view.transform_point(center);
+            let snap = |c: f32| (c / texel_size).floor() * texel_size;
+            let (x, y) = (snap(center.x), snap(center.y));
+            let projection = cgmath::ortho(
+                x - radius,
+                x + radius,
+                y - radius,
+                y + radius,
+                -center.z - radius - CASTER_DISTANCE,
+                -center.z + radius,
+            );
+            Cascade {
+                view_proj: OPENGL_TO_WGPU_MATRIX * projection * light_view,
+                split: far,
+                texel_size,
+            }
+        })
+        .collect()
+}
+
+// Cascaded shadow maps for the sun. Every frame the chunks around each piece
+// of the view are drawn into a depth texture from the sun's point of view,
+// which the main shader samples to find what the sun can't reach
+pub struct Shadows {
+    config: ShadowConfig,
+    cascade_count: usize,
+    pipeline: wgpu::RenderPipeline,
+    // Layer of the shadow map per cascade
+    cascade_views: Vec<wgpu::TextureView>,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub uniform_buffer: wgpu::Buffer,
+    matrix_buffer: wgpu::Buffer,
+    matrix_bind_group: wgpu::BindGroup,
+    // Instance ranges to draw into each cascade
+    visible: Vec<Vec<Range<u32>>>,
+}
+
+impl Shadows {
+    pub fn new(device: &wgpu::Device, config: &ShadowConfig) -> Self {
+        let cascade_count = (config.cascades as usize).min(MAX_CASCADES);
+        if cascade_count < config.cascades as usize {
+            log::warn!("at most {} shadow cascades are supported", MAX_CASCADES);
+        }
+        let resolution = config
+            .resolution
+            .clamp(1, device.limits().max_texture_dimension_2d);
+
+        // Without shadows the shader still needs something to bind
+        let size = if cascade_count == 0 {
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            }
+        } else {
+            wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: cascade_count as u32,
+            }
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_map"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..cascade_count as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        // Linear filtering compares the four nearest texels, on top of the
+        // filtering done in the shader
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+                splits: [0.0; MAX_CASCADES],
+                texel_sizes: [0.0; MAX_CASCADES],
+                cascade_count: 0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let matrix_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Matrix Buffer"),
+            size: MATRIX_STRIDE * MAX_CASCADES as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let matrix_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(64),
+                },
+                count: None,
+            }],
+            label: Some("shadow_matrix_layout"),
+        });
+        let matrix_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &matrix_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &matrix_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(64),
+                }),
+            }],
+            label: Some("shadow_matrix_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shadow.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&matrix_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            // Only depth is written
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Keeps faces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            config: ShadowConfig {
+                resolution,
+                ..config.clone()
+            },
+            cascade_count,
+            pipeline,
+            cascade_views,
+            view,
+            sampler,
+            uniform_buffer,
+            matrix_buffer,
+            matrix_bind_group,
+            visible: vec![vec![]; cascade_count],
+        }
+    }
+
+    // Fits the cascades to the camera and finds the chunks that can cast
+    // shadows into each of them
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+        sun_direction: Vector3<f32>,
+        chunks: &[ChunkInstances],
+        lod_distance: f32,
+    ) {
+        if self.cascade_count == 0 {
+            return;
+        }
+        let cascades = fit_cascades(
+            camera.calc_matrix(),
+            projection,
+            sun_direction,
+            &self.config,
+            self.cascade_count,
+        );
+
+        let mut uniform = ShadowUniform {
+            view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            splits: [0.0; MAX_CASCADES],
+            texel_sizes: [0.0; MAX_CASCADES],
+            cascade_count: self.cascade_count as u32,
+            _padding: [0; 3],
+        };
+        for (i, cascade) in cascades.iter().enumerate() {
+            uniform.view_proj[i] = cascade.view_proj.into();
+            uniform.splits[i] = cascade.split;
+            uniform.texel_sizes[i] = cascade.texel_size;
+            let matrix: [[f32; 4]; 4] = cascade.view_proj.into();
+            queue.write_buffer(
+                &self.matrix_buffer,
+                i as u64 * MATRIX_STRIDE,
+                bytemuck::cast_slice(&[matrix]),
+            );
+
+            // Chunks hidden from the camera can still cast shadows, so only
+            // the cascade's own box is checked. Levels of detail match what
+            // the camera sees so the shadows fit the terrain. Only opaque
+            // blocks cast shadows, there's no texture here to cut out gaps
+            // or tint the light
+            let frustum = Frustum::from_matrix(cascade.view_proj);
+            let visible = &mut self.visible[i];
+            visible.clear();
+            for chunk in chunks {
+                if !frustum.intersects_aabb(&chunk.aabb) {
+                    continue;
+                }
+                let instances =
+                    chunk.lod_instances(camera.position, lod_distance, Transparency::Opaque);
+                match visible.last_mut() {
+                    Some(last) if last.end == instances.start => last.end = instances.end,
+                    _ => visible.push(instances),
+                }
+            }
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Draws every cascade, has to run before the pass that samples them
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+        instance_buffer: &wgpu::Buffer,
+        index_count: u32,
+    ) {
+        for (i, view) in self.cascade_views.iter().enumerate() {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(
+                0,
+                &self.matrix_bind_group,
+                &[(i as u64 * MATRIX_STRIDE) as u32],
+            );
+            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            for instances in &self.visible[i] {
+                pass.draw_indexed(0..index_count, 0, instances.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cascades(camera: &Camera) -> Vec<Cascade> {
+        let projection = Projection::new(800, 600, cgmath::Deg(60.0), 0.1, 1000.0);
+        let sun = Vector3::new(0.4, 1.0, 0.3).normalize();
+        fit_cascades(
+            camera.calc_matrix(),
+            &projection,
+            sun,
+            &ShadowConfig::default(),
+            3,
+        )
+    }
+
+    fn in_clip_space(view_proj: Matrix4<f32>, point: Point3<f32>) -> bool {
+        let clip = view_proj * point.to_homogeneous();
+        let ndc = clip.truncate() / clip.w;
+        ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && (0.0..=1.0).contains(&ndc.z)
+    }
+
+    #[test]
+    fn splits_grow_up_to_the_shadow_distance() {
+        let cascades = cascades(&Camera::new(
+            (0.0, 50.0, 0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(-20.0),
+        ));
+        assert_eq!(cascades.len(), 3);
+        assert!(cascades
+            .windows(2)
+            .all(|pair| pair[0].split < pair[1].split));
+        assert!(cascades
+            .windows(2)
+            .all(|pair| pair[0].texel_size < pair[1].texel_size));
+        assert!((cascades[2].split - ShadowConfig::default().distance).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascades_cover_their_part_of_the_view() {
+        let camera = Camera::new((10.0, 50.0, -5.0), cgmath::Deg(30.0), cgmath::Deg(-20.0));
+        let (forward, _) = camera.horizontal_axes();
+        let cascades = cascades(&camera);
+        let mut near = 0.1;
+        for cascade in &cascades {
+            // Points along the view direction in the cascade's range, and
+            // terrain far towards the sun that could shade them
+            let depth = (near + cascade.split) / 2.0;
+            let point = camera.position + forward * depth;
+            assert!(in_clip_space(cascade.view_proj, point));
+            let caster = point + Vector3::new(0.4, 1.0, 0.3).normalize() * 200.0;
+            assert!(in_clip_space(cascade.view_proj, caster));
+            near = cascade.split;
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use winit::event::ElementState;
+
+use crate::{
+    camera::{Camera, CameraController},
+    config::Config,
+    day_night::{WorldTime, HOUR},
+    input::{Action, Input, InputEvent},
+    player::{Controls, MoveMode, Player},
+    replay::Recording,
+    world::World,
+};
+
+// Everything that changes over time, without any of the wgpu state, so it can
+// be stepped and replayed headlessly
+pub struct Simulation {
+    pub camera: Camera,
+    // Pose at the previous tick, for interpolation
+    pub previous_camera: Camera,
+    pub camera_controller: CameraController,
+    pub player: Player,
+    pub world: World,
+    pub world_time: WorldTime,
+    pub input: Input,
+    // First person look mode, where the cursor is grabbed and every mouse
+    // movement turns the camera
+    pub mouse_look: bool,
+    pub time: Duration,
+    pub exit_requested: bool,
+    recording: Option<Recording>,
+}
+
+impl Simulation {
+    pub fn new(config: &Config) -> Self {
+        let cam = &config.camera;
+        let camera = Camera::new(cam.position, cgmath::Deg(cam.yaw), cgmath::Deg(cam.pitch));
+        let camera_controller =
+            CameraController::new(cam.speed, cam.sensitivity, cam.move_speed, cam.invert_y);
+
+        let mode = if cam.fly {
+            MoveMode::Fly
+        } else {
+            MoveMode::Walk
+        };
+        let mut player = Player::new(camera.position, mode);
+        player.set_eye(camera.position);
+
+        Self {
+            previous_camera: camera.clone(),
+            camera,
+            camera_controller,
+            player,
+            world: World::generate(config.seed, config.world_size),
+            world_time: WorldTime::new(&config.day_night),
+            input: Input::new(config.bindings.clone()),
+            mouse_look: false,
+            time: Duration::ZERO,
+            exit_requested: false,
+            recording: None,
+        }
+    }
+
+    pub fn start_recording(&mut self, config: &Config) {
+        self.recording = Some(Recording::new(config.clone()));
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn process_event(&mut self, event: InputEvent) -> bool {
+        if let Some(recording) = &mut self.recording {
+            recording.push_event(event);
+        }
+
+        // Every press of Rebind moves on to the next action to rebind, so it
+        // never reaches the input itself
+        if let InputEvent::Button(binding, ElementState::Pressed) = event {
+            if self.input.map.bindings(Action::Rebind).contains(&binding) {
+                let action = self
+                    .input
+                    .rebind_target()
+                    .map_or(Action::ALL[0], Action::next);
+                log::info!(
+                    "press the new binding for {:?}, or {:?} for the next action",
+                    action,
+                    binding
+                );
+                self.input.start_rebind(action);
+                return true;
+            }
+        }
+
+        let used = match event {
+            InputEvent::MouseMotion(dx, dy) => {
+                if self.mouse_look || self.input.is_pressed(Action::Look) {
+                    self.camera_controller.process_mouse(dx, dy);
+                }
+                true
+            }
+            InputEvent::FocusLost => {
+                self.mouse_look = false;
+                self.input.process_event(event)
+            }
+            _ => self.input.process_event(event),
+        };
+        if self.input.just_pressed(Action::Exit) {
+            self.exit_requested = true;
+        }
+        used
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        if let Some(recording) = &mut self.recording {
+            recording.end_frame(self.time, dt);
+        }
+        self.time += dt;
+
+        if self.input.just_pressed(Action::ToggleMouseLook) {
+            self.mouse_look = !self.mouse_look;
+        }
+        if self.input.just_pressed(Action::ToggleFly) {
+            self.player.toggle_fly();
+        }
+        if self.input.just_pressed(Action::FreezeTime) {
+            self.world_time.frozen = !self.world_time.frozen;
+        }
+        let time_of_day = self.world_time.time_of_day();
+        if self.input.just_pressed(Action::TimeForward) {
+            self.world_time.set_time_of_day(time_of_day + HOUR);
+        }
+        if self.input.just_pressed(Action::TimeBackward) {
+            self.world_time.set_time_of_day(time_of_day - HOUR);
+        }
+        self.world_time.advance(dt);
+
+        self.previous_camera = self.camera.clone();
+        self.camera_controller.process_input(&self.input);
+        match self.player.mode {
+            MoveMode::Fly => {
+                self.camera_controller.update_camera(&mut self.camera, dt);
+                self.player.set_eye(self.camera.position);
+            }
+            MoveMode::Walk => {
+                self.camera_controller.rotate_camera(&mut self.camera);
+                let controls = Controls {
+                    wish: self.camera_controller.walk_direction(&self.camera),
+                    jump: self.input.is_pressed(Action::Jump),
+                    sprint: self.input.is_pressed(Action::Sprint),
+                    crouch: self.input.is_pressed(Action::Crouch),
+                };
+                self.player.update(&self.world, &controls, dt.as_secs_f32());
+                self.camera.position = self.player.eye();
+            }
+        }
+        self.input.end_frame();
+    }
+
+    pub fn interpolated_camera(&self, alpha: f32) -> Camera {
+        self.previous_camera.lerp(&self.camera, alpha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::VirtualKeyCode;
+
+    use super::*;
+    use crate::input::{Binding, InputMap};
+
+    fn press(sim: &mut Simulation, key: VirtualKeyCode) {
+        for state in [ElementState::Pressed, ElementState::Released] {
+            sim.process_event(InputEvent::Button(Binding::Key(key), state));
+        }
+    }
+
+    #[test]
+    fn rebind_steps_through_the_actions() {
+        let config = Config {
+            world_size: 16,
+            ..Config::default()
+        };
+        let mut sim = Simulation::new(&config);
+        press(&mut sim, VirtualKeyCode::F1);
+        assert_eq!(sim.input.rebind_target(), Some(Action::ALL[0]));
+        press(&mut sim, VirtualKeyCode::F1);
+        assert_eq!(sim.input.rebind_target(), Some(Action::ALL[1]));
+
+        press(&mut sim, VirtualKeyCode::K);
+        assert!(!sim.input.is_rebinding());
+        assert_eq!(
+            sim.input.map.bindings(Action::ALL[1]),
+            [Binding::Key(VirtualKeyCode::K)]
+        );
+        // Nothing else moved
+        assert_eq!(
+            sim.input.map.bindings(Action::ALL[0]),
+            InputMap::default().bindings(Action::ALL[0])
+        );
+    }
+}
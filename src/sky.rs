@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use anyhow::*;
+use cgmath::{Matrix4, SquareMatrix, Vector4};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{Camera, Projection},
+    day_night::DayNight,
+    resource_pack::ResourcePacks,
+    texture::{Texture, DEPTH_FORMAT},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkyMode {
+    // Gradient with stars and clouds, made up in the shader
+    Procedural,
+    // Cubemap from the resource packs
+    Skybox,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkyConfig {
+    /// Procedural or Skybox. Skybox uses the sky of the last resource pack that
+    /// has one, and falls back to Procedural if none do. Default: Procedural
+    pub mode: SkyMode,
+    /// How much of the procedural sky is covered in clouds, from 0.0 for
+    /// none to 1.0 for overcast. Default: 0.5
+    pub cloud_cover: f32,
+    /// Show stars in the procedural sky at night. Default: true
+    pub stars: bool,
+}
+
+impl Default for SkyConfig {
+    fn default() -> Self {
+        Self {
+            mode: SkyMode::Procedural,
+            cloud_cover: 0.5,
+            stars: true,
+        }
+    }
+}
+
+// Layers of the skybox to use, if the config asks for one and a pack has it
+pub fn load_skybox(config: &SkyConfig, packs: &ResourcePacks) -> Result<Option<[RgbaImage; 6]>> {
+    if config.mode != SkyMode::Skybox {
+        return Ok(None);
+    }
+    let Some(source) = packs.skybox()? else {
+        log::warn!("no resource pack has a sky, using the procedural one");
+        return Ok(None);
+    };
+    Ok(Some(source.decode()?))
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    // Seconds the simulation has run for, moves the clouds
+    time: f32,
+    daylight: f32,
+    cloud_cover: f32,
+    stars: f32,
+    // 1 to draw the cubemap instead of the procedural sky
+    skybox: u32,
+    _padding: [u32; 3],
+    // vec4s to match the alignment of vec3 in WGSL, w is unused
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    sun_direction: [f32; 4],
+    moon_direction: [f32; 4],
+}
+
+// The sky behind the terrain, coloured for the time of day
+pub struct Sky {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    bind_group: wgpu::BindGroup,
+    config: SkyConfig,
+    has_skybox: bool,
+}
+
+impl Sky {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        config: &SkyConfig,
+        skybox: Option<&[RgbaImage; 6]>,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sky_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, queue, &layout, &buffer, skybox);
+
+        let pipeline = Self::create_pipeline(device, &layout, format, sample_count);
+
+        Self {
+            pipeline,
+            buffer,
+            layout,
+            format,
+            bind_group,
+            config: config.clone(),
+            has_skybox: skybox.is_some(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("sky.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn first in the terrain's pass on the far plane, so it only
+            // shows where nothing else ends up and leaves the depth alone
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    // Has to match the target it's drawn into
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(device, &self.layout, self.format, sample_count);
+    }
+
+    // Without a skybox the shader still needs a cubemap bound, a black one
+    // that's never sampled
+    fn create_bind_group(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        skybox: Option<&[RgbaImage; 6]>,
+    ) -> wgpu::BindGroup {
+        let empty;
+        let layers = match skybox {
+            Some(layers) => layers,
+            None => {
+                empty = std::array::from_fn(|_| RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])));
+                &empty
+            }
+        };
+        let texture = Texture::from_cube(device, queue, layers, Some("skybox"));
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("sky_bind_group"),
+        })
+    }
+
+    pub fn config(&self) -> &SkyConfig {
+        &self.config
+    }
+
+    pub fn set_skybox(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        skybox: Option<&[RgbaImage; 6]>,
+    ) {
+        self.bind_group =
+            Self::create_bind_group(device, queue, &self.layout, &self.buffer, skybox);
+        self.has_skybox = skybox.is_some();
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+        day_night: &DayNight,
+        time: Duration,
+    ) {
+        // Only the direction of each pixel matters, leaving out where the camera
+        // is keeps the directions precise far from the origin
+        let mut view = camera.calc_matrix();
+        view.w = Vector4::unit_w();
+        let view_proj = projection.calc_matrix() * view;
+        let vec4 = |v: cgmath::Vector3<f32>| v.extend(0.0).into();
+        let uniform = SkyUniform {
+            inverse_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()).into(),
+            time: time.as_secs_f32(),
+            daylight: day_night.daylight,
+            cloud_cover: self.config.cloud_cover.clamp(0.0, 1.0),
+            stars: if self.config.stars { 1.0 } else { 0.0 },
+            skybox: self.has_skybox as u32,
+            _padding: [0; 3],
+            zenith: vec4(day_night.zenith),
+            horizon: vec4(day_night.horizon),
+            sun_direction: vec4(day_night.sun_direction),
+            moon_direction: vec4(day_night.moon_direction),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
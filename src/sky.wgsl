@@ -0,0 +1,157 @@
+// Sky gradient or skybox with the sun and moon, drawn behind everything else
+
+struct Sky {
+    // From clip space back to world space around the camera, to find the
+    // direction of each pixel
+    inverse_view_proj: mat4x4<f32>,
+    // Seconds since the simulation started
+    time: f32,
+    // 1.0 by day and 0.0 at night
+    daylight: f32,
+    cloud_cover: f32,
+    // 1.0 to show stars, 0.0 to hide them
+    stars: f32,
+    // 1 to draw the cubemap instead of the gradient, stars and clouds
+    skybox: u32,
+    zenith: vec3<f32>,
+    horizon: vec3<f32>,
+    // Towards the sun and moon, normalized
+    sun_direction: vec3<f32>,
+    moon_direction: vec3<f32>,
+};
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+@group(0) @binding(1)
+var t_skybox: texture_cube<f32>;
+@group(0) @binding(2)
+var s_skybox: sampler;
+
+// Fraction of the sky with a star in it, and how finely it's divided up
+const STAR_DENSITY: f32 = 0.0015;
+const STAR_CELLS: f32 = 400.0;
+// How fast the clouds drift across the sky
+const CLOUD_WIND: vec2<f32> = vec2<f32>(0.004, 0.0015);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// One triangle that covers the whole screen, on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+fn world_position(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let position = sky.inverse_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+// Random number from 0 to 1 for each cell of a grid
+fn hash(cell: vec3<i32>) -> f32 {
+    var h = (bitcast<u32>(cell.x) * 73856093u) ^ (bitcast<u32>(cell.y) * 19349663u) ^ (bitcast<u32>(cell.z) * 83492791u);
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = h ^ (h >> 16u);
+    return f32(h) / 4294967295.0;
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = fract(p);
+    let t = f * f * (3.0 - 2.0 * f);
+    let a = hash(vec3<i32>(cell, 0));
+    let b = hash(vec3<i32>(cell + vec2<i32>(1, 0), 0));
+    let c = hash(vec3<i32>(cell + vec2<i32>(0, 1), 0));
+    let d = hash(vec3<i32>(cell + vec2<i32>(1, 1), 0));
+    return mix(mix(a, b, t.x), mix(c, d, t.x), t.y);
+}
+
+// Several octaves of noise, each twice as fine and half as strong
+fn fbm(p: vec2<f32>) -> f32 {
+    var value = 0.0;
+    var amplitude = 0.5;
+    var q = p;
+    for (var i = 0; i < 5; i++) {
+        value += value_noise(q) * amplitude;
+        q = q * 2.03 + vec2<f32>(17.1, 4.7);
+        amplitude *= 0.5;
+    }
+    return value / 0.96875;
+}
+
+fn stars(direction: vec3<f32>) -> vec3<f32> {
+    let cell = vec3<i32>(floor(direction * STAR_CELLS));
+    let star = hash(cell);
+    if star > STAR_DENSITY {
+        return vec3<f32>(0.0);
+    }
+    // Dimmer and bluer or redder at random, twinkling slowly
+    let brightness = star / STAR_DENSITY;
+    let twinkle = 0.75 + 0.25 * sin(sky.time * (1.0 + brightness * 3.0) + brightness * 50.0);
+    let tint = mix(vec3<f32>(1.0, 0.85, 0.7), vec3<f32>(0.75, 0.85, 1.0), hash(cell + vec3<i32>(7)));
+    return tint * (0.3 + 0.7 * brightness) * twinkle;
+}
+
+// How much of the pixel is covered in cloud, and its colour
+fn clouds(direction: vec3<f32>) -> vec4<f32> {
+    if direction.y <= 0.0 || sky.cloud_cover <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    let position = direction.xz / direction.y + sky.time * CLOUD_WIND;
+    let density = fbm(position * 3.0);
+    let threshold = 1.0 - sky.cloud_cover;
+    var cover = smoothstep(threshold - 0.1, threshold + 0.25, density);
+    // Thin out towards the horizon where the layer gets squashed up
+    cover *= smoothstep(0.02, 0.2, direction.y);
+
+    // White by day and barely lit at night, tinted by the horizon at sunset
+    // and brighter towards the sun
+    let lit = mix(vec3<f32>(0.05, 0.06, 0.09), vec3<f32>(1.0), sky.daylight);
+    let glow = pow(max(dot(direction, sky.sun_direction), 0.0), 8.0) * sky.daylight;
+    let colour = mix(lit, sky.horizon, 0.35) * (1.0 - density * 0.35) + glow * 0.3;
+    return vec4<f32>(colour, cover);
+}
+
+fn procedural(direction: vec3<f32>) -> vec3<f32> {
+    // Brightest at the horizon and fading into the zenith colour above it,
+    // below the horizon stays a little darker than at it
+    let height = direction.y;
+    var colour = mix(sky.horizon, sky.zenith, sqrt(max(height, 0.0)));
+    colour = mix(colour, sky.horizon * 0.6, clamp(-height * 4.0, 0.0, 1.0));
+
+    let night = (1.0 - sky.daylight) * sky.stars * smoothstep(-0.02, 0.1, height);
+    colour += stars(direction) * night;
+
+    let cloud = clouds(direction);
+    return mix(colour, cloud.rgb, cloud.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(world_position(in.ndc, 1.0) - world_position(in.ndc, 0.0));
+
+    var colour: vec3<f32>;
+    if sky.skybox != 0u {
+        // The cubemap is left handed, see skybox.rs
+        let sampled = textureSample(t_skybox, s_skybox, vec3<f32>(direction.xy, -direction.z)).rgb;
+        colour = sampled * mix(0.05, 1.0, sky.daylight);
+    } else {
+        colour = procedural(direction);
+    }
+
+    let sun = dot(direction, sky.sun_direction);
+    let sun_up = smoothstep(-0.05, 0.05, sky.sun_direction.y);
+    colour += vec3<f32>(1.0, 0.9, 0.7) * pow(max(sun, 0.0), 64.0) * 0.4 * sun_up;
+    colour = mix(colour, vec3<f32>(1.0, 0.95, 0.8), smoothstep(0.9990, 0.9995, sun));
+
+    let moon = dot(direction, sky.moon_direction);
+    colour = mix(colour, vec3<f32>(0.8, 0.82, 0.9), smoothstep(0.9994, 0.9997, moon));
+
+    return vec4<f32>(colour, 1.0);
+}
@@ -0,0 +1,172 @@
+use std::f32::consts::{PI, TAU};
+
+use anyhow::*;
+use cgmath::{InnerSpace, Vector3};
+use image::{imageops, RgbaImage};
+
+// File names of the six images in a skybox directory, seen looking towards +x,
+// -x, +y, -y, +z and -z. The sides are upright as seen from inside the box, the
+// top and bottom line up with the -z side
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+// Images a skybox is made from, still encoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkyboxSource {
+    // In the order of FACE_NAMES
+    Faces([Vec<u8>; 6]),
+    // The whole sky in one image, 360 degrees across and 180 down
+    Equirectangular(Vec<u8>),
+}
+
+impl SkyboxSource {
+    // The six layers of the cubemap, see `layer_direction` for their order
+    pub fn decode(&self) -> Result<[RgbaImage; 6]> {
+        match self {
+            SkyboxSource::Faces(faces) => {
+                let mut images = Vec::with_capacity(6);
+                for (bytes, name) in faces.iter().zip(FACE_NAMES) {
+                    let image = image::load_from_memory(bytes)
+                        .with_context(|| format!("failed to load skybox face {}", name))?
+                        .to_rgba8();
+                    ensure!(
+                        image.width() == image.height(),
+                        "skybox face {} is {:?} but has to be square",
+                        name,
+                        image.dimensions()
+                    );
+                    images.push(image);
+                }
+                let size = images[0].dimensions();
+                if let Some(i) = images.iter().position(|i| i.dimensions() != size) {
+                    bail!(
+                        "skybox face {} is {:?} but {} is {:?}",
+                        FACE_NAMES[i],
+                        images[i].dimensions(),
+                        FACE_NAMES[0],
+                        size
+                    );
+                }
+                let [px, nx, py, ny, pz, nz] = images.try_into().unwrap();
+                Ok([px, nx, py, ny, nz, pz])
+            }
+            SkyboxSource::Equirectangular(bytes) => {
+                let image = image::load_from_memory(bytes)
+                    .context("failed to load skybox image")?
+                    .to_rgba8();
+                ensure!(
+                    image.width() >= 4 && image.height() >= 2,
+                    "skybox image is too small"
+                );
+                Ok(equirectangular_to_cube(&image))
+            }
+        }
+    }
+}
+
+// Direction in the world of a point on a layer of the cubemap, with u and v
+// going from -1 to 1 across and down it. The sky shader looks up the cubemap
+// with z flipped, which makes the usual cubemap layout right handed like the
+// world, but puts the -z side on the +z layer and the other way round
+pub fn layer_direction(layer: usize, u: f32, v: f32) -> Vector3<f32> {
+    match layer {
+        0 => Vector3::new(1.0, -v, u),
+        1 => Vector3::new(-1.0, -v, -u),
+        2 => Vector3::new(u, 1.0, -v),
+        3 => Vector3::new(u, -1.0, v),
+        4 => Vector3::new(u, -v, -1.0),
+        _ => Vector3::new(-u, -v, 1.0),
+    }
+}
+
+// Projects an equirectangular image onto the cubemap layers, each a quarter of
+// its width across. The middle of the image ends up towards -z
+fn equirectangular_to_cube(image: &RgbaImage) -> [RgbaImage; 6] {
+    let size = image.width() / 4;
+    std::array::from_fn(|layer| {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let direction = layer_direction(layer, u, v).normalize();
+            let longitude = direction.x.atan2(-direction.z);
+            let latitude = direction.y.clamp(-1.0, 1.0).acos();
+            let (s, t) = (longitude / TAU + 0.5, latitude / PI);
+            imageops::sample_bilinear(image, s.clamp(0.0, 1.0), t.clamp(0.0, 1.0)).unwrap()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageFormat, Rgba};
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn png(image: &RgbaImage) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn layers_face_their_own_side() {
+        let axes = [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            -Vector3::unit_z(),
+            Vector3::unit_z(),
+        ];
+        for (layer, axis) in axes.iter().enumerate() {
+            assert_eq!(layer_direction(layer, 0.0, 0.0), *axis);
+            // Across a side layer goes to the right and down goes down, as seen
+            // looking at it from inside
+            if axis.y == 0.0 {
+                let right = axis.cross(Vector3::unit_y());
+                assert_eq!(layer_direction(layer, 1.0, 0.0) - axis, right);
+                assert_eq!(layer_direction(layer, 0.0, 1.0) - axis, -Vector3::unit_y());
+            }
+        }
+    }
+
+    #[test]
+    fn faces_are_put_on_their_layers() {
+        let faces = std::array::from_fn(|i| png(&RgbaImage::from_pixel(2, 2, Rgba([i as u8; 4]))));
+        let layers = SkyboxSource::Faces(faces).decode().unwrap();
+        let firsts = layers.map(|layer| layer.get_pixel(0, 0)[0]);
+        assert_eq!(firsts, [0, 1, 2, 3, 5, 4]);
+    }
+
+    #[test]
+    fn faces_have_to_match() {
+        let square = png(&RgbaImage::new(4, 4));
+        let mut faces: [Vec<u8>; 6] = std::array::from_fn(|_| square.clone());
+        faces[3] = png(&RgbaImage::new(2, 2));
+        assert!(SkyboxSource::Faces(faces.clone()).decode().is_err());
+        faces[3] = png(&RgbaImage::new(4, 2));
+        assert!(SkyboxSource::Faces(faces.clone()).decode().is_err());
+        faces[3] = b"not an image".to_vec();
+        assert!(SkyboxSource::Faces(faces).decode().is_err());
+    }
+
+    #[test]
+    fn equirectangular_wraps_around_the_cube() {
+        // White sky over black ground, with a red band down the middle
+        let image = RgbaImage::from_fn(64, 32, |x, y| match (x, y) {
+            (28..=35, _) => Rgba([255, 0, 0, 255]),
+            (_, 0..=15) => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        });
+        let layers = SkyboxSource::Equirectangular(png(&image)).decode().unwrap();
+        assert_eq!(layers[0].dimensions(), (16, 16));
+
+        let top = layers[2].get_pixel(3, 3);
+        let bottom = layers[3].get_pixel(3, 3);
+        assert_eq!(top, &Rgba([255, 255, 255, 255]));
+        assert_eq!(bottom, &Rgba([0, 0, 0, 255]));
+        // The middle of the image is towards -z, which is on layer 4
+        assert_eq!(layers[4].get_pixel(8, 4)[1], 0);
+        assert_eq!(layers[5].get_pixel(8, 4), &Rgba([255, 255, 255, 255]));
+    }
+}
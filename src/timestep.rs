@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+// Turns variable frame times into a whole number of fixed simulation ticks.
+// Whatever doesn't fill a tick is carried over to the next frame and exposed
+// through `alpha` so rendering can interpolate between the last two ticks.
+#[derive(Debug)]
+pub struct FixedTimestep {
+    dt: Duration,
+    accumulator: Duration,
+    max_ticks: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f64, max_ticks: u32) -> Self {
+        Self {
+            dt: Duration::from_secs_f64(1.0 / tick_rate),
+            accumulator: Duration::ZERO,
+            max_ticks: max_ticks.max(1),
+        }
+    }
+
+    pub fn dt(&self) -> Duration {
+        self.dt
+    }
+
+    // Returns how many ticks should be simulated for a frame that took `frame_dt`.
+    // If the simulation can't keep up the backlog is dropped instead of growing
+    // every frame (the spiral of death)
+    pub fn advance(&mut self, frame_dt: Duration) -> u32 {
+        self.accumulator += frame_dt;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.dt {
+            if ticks == self.max_ticks {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= self.dt;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    // How far we are between the previous and the current tick, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.dt.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn advances_whole_ticks() {
+        let mut timestep = FixedTimestep::new(100.0, 5);
+        assert_eq!(timestep.dt(), ms(10));
+        assert_eq!(timestep.advance(ms(5)), 0);
+        assert_eq!(timestep.advance(ms(5)), 1);
+        assert_eq!(timestep.advance(ms(35)), 3);
+        // The leftover 5ms carries over
+        assert_eq!(timestep.advance(ms(5)), 1);
+    }
+
+    #[test]
+    fn drops_the_backlog_past_max_ticks() {
+        let mut timestep = FixedTimestep::new(100.0, 5);
+        assert_eq!(timestep.advance(ms(1000)), 5);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(ms(10)), 1);
+    }
+
+    #[test]
+    fn alpha_is_the_fraction_of_a_tick_left_over() {
+        let mut timestep = FixedTimestep::new(100.0, 5);
+        assert_eq!(timestep.alpha(), 0.0);
+        timestep.advance(ms(25));
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        timestep.advance(ms(4));
+        assert!((timestep.alpha() - 0.9).abs() < 1e-4);
+    }
+}
@@ -1,3 +1,4 @@
+use cgmath::Vector3;
 use wgpu::{util::DeviceExt, Buffer, Device};
 
 #[repr(C)]
@@ -5,8 +6,16 @@ use wgpu::{util::DeviceExt, Buffer, Device};
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    face: u32,
+    normal: [f32; 3],
 }
 impl Vertex {
+    // Which corner of the block the vertex is on, -1 or 1 along every axis
+    pub fn corner(&self) -> Vector3<i32> {
+        let [x, y, z] = self.position;
+        Vector3::new(x as i32, y as i32, z as i32 - 1)
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -22,6 +31,16 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -43,71 +62,49 @@ pub fn generate_buffers(device: &Device) -> (Buffer, Buffer) {
     (vertex_buffer, index_buffer)
 }
 
+// The cube spans -1..1 on x and y and 0..2 on z. Every face has its own four
+// vertices so it can have its own texture, `face` indexes NEIGHBOURS. Corners
+// go counter clockwise seen from outside, starting at the bottom left of the texture
 #[rustfmt::skip]
 pub const VERTICES: &[Vertex] = &[
-    //0, 1, 2, 3
-    Vertex {
-        position: [-1.0, -1.0, 0.0],
-        tex_coords: [0.0, 1.0],
-    },
-    Vertex {
-        position: [1.0, -1.0, 0.0],
-        tex_coords: [0.5, 1.0],
-    },
-    Vertex {
-        position: [1.0, 1.0, 0.0],
-        tex_coords: [0.5, 0.0],
-    },
-    Vertex {
-        position: [-1.0, 1.0, 0.0],
-        tex_coords: [0.0, 0.0],
-    },
-
-    //4, 5, 6, 7
-    Vertex {
-        position: [-1.0, -1.0, 2.0],
-        tex_coords: [0.5, 1.0],
-    },
-    Vertex {
-        position: [1.0, -1.0, 2.0],
-        tex_coords: [0.0, 1.0],
-    },
-    Vertex {
-        position: [1.0, 1.0, 2.0],
-        tex_coords: [0.0, 0.0],
-    },
-    Vertex {
-        position: [-1.0, 1.0, 2.0],
-        tex_coords: [0.5, 0.0],
-    },
-
-    //8, 9, 10, 11, grass tex
-    Vertex { 
-        position: [1.0, 1.0, 0.0],  //front right
-        tex_coords: [1.0, 1.0],
-    },
-    Vertex {
-        position: [-1.0, 1.0, 0.0], //front left
-        tex_coords: [0.5, 1.0],
-    },
-    Vertex {
-        position: [1.0, 1.0, 2.0],  //back right
-        tex_coords: [1.0, 0.0],
-    },
-    Vertex {
-        position: [-1.0, 1.0, 2.0], //back left
-        tex_coords: [0.5, 0.0],
-    },
+    // +x
+    Vertex { position: [1.0, -1.0, 2.0], tex_coords: [0.0, 1.0], face: 0, normal: [1.0, 0.0, 0.0] },
+    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [1.0, 1.0], face: 0, normal: [1.0, 0.0, 0.0] },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], face: 0, normal: [1.0, 0.0, 0.0] },
+    Vertex { position: [1.0, 1.0, 2.0], tex_coords: [0.0, 0.0], face: 0, normal: [1.0, 0.0, 0.0] },
+    // -x
+    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [0.0, 1.0], face: 1, normal: [-1.0, 0.0, 0.0] },
+    Vertex { position: [-1.0, -1.0, 2.0], tex_coords: [1.0, 1.0], face: 1, normal: [-1.0, 0.0, 0.0] },
+    Vertex { position: [-1.0, 1.0, 2.0], tex_coords: [1.0, 0.0], face: 1, normal: [-1.0, 0.0, 0.0] },
+    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [0.0, 0.0], face: 1, normal: [-1.0, 0.0, 0.0] },
+    // +y
+    Vertex { position: [-1.0, 1.0, 2.0], tex_coords: [0.0, 1.0], face: 2, normal: [0.0, 1.0, 0.0] },
+    Vertex { position: [1.0, 1.0, 2.0], tex_coords: [1.0, 1.0], face: 2, normal: [0.0, 1.0, 0.0] },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], face: 2, normal: [0.0, 1.0, 0.0] },
+    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [0.0, 0.0], face: 2, normal: [0.0, 1.0, 0.0] },
+    // -y
+    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [0.0, 1.0], face: 3, normal: [0.0, -1.0, 0.0] },
+    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [1.0, 1.0], face: 3, normal: [0.0, -1.0, 0.0] },
+    Vertex { position: [1.0, -1.0, 2.0], tex_coords: [1.0, 0.0], face: 3, normal: [0.0, -1.0, 0.0] },
+    Vertex { position: [-1.0, -1.0, 2.0], tex_coords: [0.0, 0.0], face: 3, normal: [0.0, -1.0, 0.0] },
+    // +z
+    Vertex { position: [-1.0, -1.0, 2.0], tex_coords: [0.0, 1.0], face: 4, normal: [0.0, 0.0, 1.0] },
+    Vertex { position: [1.0, -1.0, 2.0], tex_coords: [1.0, 1.0], face: 4, normal: [0.0, 0.0, 1.0] },
+    Vertex { position: [1.0, 1.0, 2.0], tex_coords: [1.0, 0.0], face: 4, normal: [0.0, 0.0, 1.0] },
+    Vertex { position: [-1.0, 1.0, 2.0], tex_coords: [0.0, 0.0], face: 4, normal: [0.0, 0.0, 1.0] },
+    // -z
+    Vertex { position: [1.0, -1.0, 0.0], tex_coords: [0.0, 1.0], face: 5, normal: [0.0, 0.0, -1.0] },
+    Vertex { position: [-1.0, -1.0, 0.0], tex_coords: [1.0, 1.0], face: 5, normal: [0.0, 0.0, -1.0] },
+    Vertex { position: [-1.0, 1.0, 0.0], tex_coords: [1.0, 0.0], face: 5, normal: [0.0, 0.0, -1.0] },
+    Vertex { position: [1.0, 1.0, 0.0], tex_coords: [0.0, 0.0], face: 5, normal: [0.0, 0.0, -1.0] },
 ];
+
 #[rustfmt::skip]
 pub const INDICES: &[u16] = &[
-    0, 1, 2, 2, 3, 0, 
+    0, 1, 2, 2, 3, 0,
     4, 5, 6, 6, 7, 4,
-    1, 2, 5, 6, 5, 2,
-    0, 3, 4, 3, 4, 7,
-
-    8, 9, 10, 10, 11, 9
-    // 0, 1, 4, 4, 1, 5
-    ];
-
-//pub const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+    8, 9, 10, 10, 11, 8,
+    12, 13, 14, 14, 15, 12,
+    16, 17, 18, 18, 19, 16,
+    20, 21, 22, 22, 23, 20,
+];
@@ -0,0 +1,391 @@
+use std::collections::VecDeque;
+
+use cgmath::Vector3;
+
+use crate::{
+    block::BlockRegistry,
+    world::{Chunk, World, CHUNK_SIZE, NEIGHBOURS},
+};
+
+// Which faces of a chunk can see each other through non-opaque blocks. Faces
+// are indices into NEIGHBOURS, every pair gets a bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connectivity(u64);
+
+impl Connectivity {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    pub fn connected(self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+
+    fn connect(&mut self, a: usize, b: usize) {
+        self.0 |= 1 << (a * 6 + b) | 1 << (b * 6 + a);
+    }
+
+    // Flood fills every region of non-opaque blocks in the chunk and connects
+    // all the faces each region touches
+    pub fn of_chunk(chunk: &Chunk, blocks: &BlockRegistry) -> Self {
+        let blocks = match chunk {
+            Chunk::Uniform(block) if blocks.get(*block).opaque => return Self::NONE,
+            Chunk::Uniform(_) => return Self::ALL,
+            Chunk::Dense(chunk_blocks) => chunk_blocks.map(|block| blocks.get(block).opaque),
+        };
+        const SIZE: usize = CHUNK_SIZE as usize;
+        // Index offset along x, y and z, the +/- faces of axis i are 2i and 2i + 1
+        // like in NEIGHBOURS
+        const STRIDES: [usize; 3] = [1, SIZE, SIZE * SIZE];
+
+        let mut connectivity = Self::NONE;
+        let mut visited = blocks;
+        let mut stack = vec![];
+        for start in 0..visited.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+
+            let mut faces = [false; 6];
+            while let Some(index) = stack.pop() {
+                for (axis, stride) in STRIDES.iter().enumerate() {
+                    let coord = index / stride % SIZE;
+                    for (face, next) in [
+                        (axis * 2, (coord + 1 < SIZE).then(|| index + stride)),
+                        (axis * 2 + 1, (coord > 0).then(|| index - stride)),
+                    ] {
+                        match next {
+                            None => faces[face] = true,
+                            Some(next) if !visited[next] => {
+                                visited[next] = true;
+                                stack.push(next);
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                }
+            }
+
+            for a in 0..6 {
+                for b in 0..6 {
+                    if faces[a] && faces[b] {
+                        connectivity.connect(a, b);
+                    }
+                }
+            }
+            if connectivity == Self::ALL {
+                break;
+            }
+        }
+        connectivity
+    }
+}
+
+// Per chunk connectivity, used to find the chunks the camera could possibly see
+// without looking through solid terrain. Stored as a grid over the chunks of
+// the world
+#[derive(Debug, Clone)]
+pub struct VisibilityGraph {
+    grid: ChunkGrid<Connectivity>,
+}
+
+// Covers no chunks, so nothing is ruled out
+impl Default for VisibilityGraph {
+    fn default() -> Self {
+        let origin = Vector3::new(0, 0, 0);
+        Self {
+            grid: ChunkGrid::new(origin, origin - Vector3::new(1, 1, 1), Connectivity::ALL),
+        }
+    }
+}
+
+impl VisibilityGraph {
+    pub fn new(world: &World) -> Self {
+        let mut min = Vector3::new(i32::MAX, i32::MAX, i32::MAX);
+        let mut max = Vector3::new(i32::MIN, i32::MIN, i32::MIN);
+        for (pos, _) in world.chunks() {
+            min = min.zip(*pos, i32::min);
+            max = max.zip(*pos, i32::max);
+        }
+
+        // Missing chunks are air
+        let mut grid = ChunkGrid::new(min, max, Connectivity::ALL);
+        for (pos, chunk) in world.chunks() {
+            *grid.get_mut(*pos).unwrap() = Connectivity::of_chunk(chunk, &world.blocks);
+        }
+        Self { grid }
+    }
+
+    // Recomputes the connectivity of a chunk after its blocks changed
+    pub fn update_chunk(&mut self, world: &World, pos: Vector3<i32>) {
+        let connectivity = world.chunk(pos).map_or(Connectivity::ALL, |chunk| {
+            Connectivity::of_chunk(chunk, &world.blocks)
+        });
+        match self.grid.get_mut(pos) {
+            Some(current) => *current = connectivity,
+            // A chunk that wasn't there before, the grid has to grow
+            None => *self = Self::new(world),
+        }
+    }
+
+    // Breadth first search from the camera's chunk. A chunk is only entered
+    // through a face that the previous chunk connects to the face it was entered
+    // from, and the search never turns back towards the camera. Returns None when
+    // the camera is outside the world, then nothing can be ruled out
+    pub fn potentially_visible(&self, camera: Vector3<i32>) -> Option<VisibleSet> {
+        // The space above the terrain is searched too
+        let min = self.grid.min;
+        let max = self.grid.max();
+        let mut visible =
+            ChunkGrid::new(min, Vector3::new(max.x, max.y.max(camera.y), max.z), false);
+        *visible.get_mut(camera)? = true;
+
+        // Position, face it was entered through and the directions travelled so far
+        let mut queue = VecDeque::from([(camera, None, 0u8)]);
+        while let Some((pos, entered, directions)) = queue.pop_front() {
+            let connectivity = self.grid.get(pos).unwrap_or(Connectivity::ALL);
+            for (face, offset) in NEIGHBOURS.iter().enumerate() {
+                if directions & (1 << (face ^ 1)) != 0
+                    || entered.is_some_and(|entered| !connectivity.connected(entered, face))
+                {
+                    continue;
+                }
+                let next = pos + offset;
+                match visible.get_mut(next) {
+                    Some(seen) if !*seen => *seen = true,
+                    _ => continue,
+                }
+                queue.push_back((next, Some(face ^ 1), directions | 1 << face));
+            }
+        }
+        Some(VisibleSet(visible))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VisibleSet(ChunkGrid<bool>);
+
+impl VisibleSet {
+    pub fn contains(&self, pos: Vector3<i32>) -> bool {
+        self.0.get(pos).unwrap_or(false)
+    }
+}
+
+// A value for every chunk position in a box
+#[derive(Debug, Clone)]
+struct ChunkGrid<T> {
+    min: Vector3<i32>,
+    size: Vector3<i32>,
+    values: Vec<T>,
+}
+
+impl<T: Copy> ChunkGrid<T> {
+    fn new(min: Vector3<i32>, max: Vector3<i32>, value: T) -> Self {
+        let size = (max - min + Vector3::new(1, 1, 1)).map(|c| c.max(0));
+        Self {
+            min,
+            size,
+            values: vec![value; (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    fn max(&self) -> Vector3<i32> {
+        self.min + self.size - Vector3::new(1, 1, 1)
+    }
+
+    fn index(&self, pos: Vector3<i32>) -> Option<usize> {
+        let local = pos - self.min;
+        let inside = |c: i32, size: i32| (0..size).contains(&c);
+        (inside(local.x, self.size.x)
+            && inside(local.y, self.size.y)
+            && inside(local.z, self.size.z))
+        .then(|| (local.x + (local.y + local.z * self.size.y) * self.size.x) as usize)
+    }
+
+    fn get(&self, pos: Vector3<i32>) -> Option<T> {
+        self.index(pos).map(|i| self.values[i])
+    }
+
+    fn get_mut(&mut self, pos: Vector3<i32>) -> Option<&mut T> {
+        self.index(pos).map(|i| &mut self.values[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{AIR, DIRT};
+
+    fn fill(world: &mut World, chunk: Vector3<i32>, block: u8) {
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    world.set_block(chunk * CHUNK_SIZE + Vector3::new(x, y, z), block);
+                }
+            }
+        }
+    }
+
+    // Two 3x3 layers of solid chunks at chunk y -1 and 0
+    fn solid_ground() -> World {
+        let mut world = World::new();
+        for y in -1..=0 {
+            for z in 0..3 {
+                for x in 0..3 {
+                    fill(&mut world, Vector3::new(x, y, z), DIRT);
+                }
+            }
+        }
+        world
+    }
+
+    // Vertical shaft through chunk (x, 0, z)
+    fn dig_shaft(world: &mut World, x: i32, z: i32) {
+        for y in 0..CHUNK_SIZE {
+            world.set_block(Vector3::new(x * CHUNK_SIZE + 5, y, z * CHUNK_SIZE + 5), AIR);
+        }
+    }
+
+    #[test]
+    fn uniform_chunks() {
+        let blocks = BlockRegistry::default();
+        assert_eq!(
+            Connectivity::of_chunk(&Chunk::Uniform(DIRT), &blocks),
+            Connectivity::NONE
+        );
+        assert_eq!(
+            Connectivity::of_chunk(&Chunk::Uniform(AIR), &blocks),
+            Connectivity::ALL
+        );
+    }
+
+    #[test]
+    fn tunnel_connects_its_ends_only() {
+        let mut world = World::new();
+        fill(&mut world, Vector3::new(0, 0, 0), DIRT);
+        for x in 0..CHUNK_SIZE {
+            world.set_block(Vector3::new(x, 5, 5), AIR);
+        }
+
+        let connectivity =
+            Connectivity::of_chunk(world.chunk(Vector3::new(0, 0, 0)).unwrap(), &world.blocks);
+        assert!(connectivity.connected(0, 1));
+        assert!(connectivity.connected(1, 0));
+        for face in 2..6 {
+            assert!(!connectivity.connected(0, face));
+            assert!(!connectivity.connected(1, face));
+        }
+    }
+
+    #[test]
+    fn separate_pockets_stay_separate() {
+        let mut world = World::new();
+        fill(&mut world, Vector3::new(0, 0, 0), DIRT);
+        // A hole in the -x face and one in the +y face that don't meet
+        world.set_block(Vector3::new(0, 3, 3), AIR);
+        world.set_block(Vector3::new(8, CHUNK_SIZE - 1, 8), AIR);
+
+        let connectivity =
+            Connectivity::of_chunk(world.chunk(Vector3::new(0, 0, 0)).unwrap(), &world.blocks);
+        assert!(!connectivity.connected(1, 2));
+        assert!(connectivity.connected(1, 1));
+        assert!(connectivity.connected(2, 2));
+    }
+
+    #[test]
+    fn solid_terrain_hides_what_is_below() {
+        let mut world = solid_ground();
+        fill(&mut world, Vector3::new(1, -1, 1), AIR);
+
+        let graph = VisibilityGraph::new(&world);
+        let visible = graph.potentially_visible(Vector3::new(1, 1, 1)).unwrap();
+        assert!(visible.contains(Vector3::new(1, 0, 1)));
+        assert!(!visible.contains(Vector3::new(1, -1, 1)));
+    }
+
+    #[test]
+    fn shaft_reveals_cave() {
+        let mut world = solid_ground();
+        fill(&mut world, Vector3::new(1, -1, 1), AIR);
+        fill(&mut world, Vector3::new(2, -1, 1), AIR);
+        dig_shaft(&mut world, 1, 1);
+
+        let graph = VisibilityGraph::new(&world);
+        let visible = graph.potentially_visible(Vector3::new(1, 1, 1)).unwrap();
+        assert!(visible.contains(Vector3::new(1, -1, 1)));
+        assert!(visible.contains(Vector3::new(2, -1, 1)));
+        // Solid, so it's entered but not looked through
+        assert!(visible.contains(Vector3::new(0, -1, 1)));
+        assert!(!visible.contains(Vector3::new(0, -1, 0)));
+    }
+
+    #[test]
+    fn search_never_turns_back() {
+        let mut world = solid_ground();
+        fill(&mut world, Vector3::new(0, -1, 0), AIR);
+        fill(&mut world, Vector3::new(1, -1, 0), AIR);
+        dig_shaft(&mut world, 0, 0);
+        // Covered from above, only open to the cave below it
+        fill(&mut world, Vector3::new(1, 1, 0), DIRT);
+        world.set_block(Vector3::new(CHUNK_SIZE + 8, 0, 8), AIR);
+
+        let graph = VisibilityGraph::new(&world);
+        let visible = graph.potentially_visible(Vector3::new(0, 1, 0)).unwrap();
+        assert!(visible.contains(Vector3::new(1, -1, 0)));
+        // Reaching it from the cave means going back up
+        assert!(!visible.contains(Vector3::new(1, 0, 0)));
+    }
+
+    #[test]
+    fn outside_the_world() {
+        let graph = VisibilityGraph::new(&solid_ground());
+        assert!(graph.potentially_visible(Vector3::new(10, 0, 0)).is_none());
+        // Above the world still works
+        assert!(graph.potentially_visible(Vector3::new(1, 10, 1)).is_some());
+    }
+
+    #[test]
+    fn placing_blocks_updates_the_graph() {
+        let mut world = solid_ground();
+        fill(&mut world, Vector3::new(1, -1, 1), AIR);
+        world.visibility = VisibilityGraph::new(&world);
+        let cave = Vector3::new(1, -1, 1);
+        let camera = Vector3::new(1, 1, 1);
+        assert!(!world
+            .visibility
+            .potentially_visible(camera)
+            .unwrap()
+            .contains(cave));
+
+        let shaft = Vector3::new(CHUNK_SIZE + 5, 0, CHUNK_SIZE + 5);
+        for y in 0..CHUNK_SIZE {
+            world.place_block(shaft + Vector3::new(0, y, 0), AIR);
+        }
+        assert!(world
+            .visibility
+            .potentially_visible(camera)
+            .unwrap()
+            .contains(cave));
+
+        world.place_block(shaft, DIRT);
+        // Tells the engine its potentially visible set is out of date
+        assert_eq!(world.generation(), CHUNK_SIZE as u64 + 1);
+        assert!(!world
+            .visibility
+            .potentially_visible(camera)
+            .unwrap()
+            .contains(cave));
+    }
+
+    #[test]
+    fn new_chunks_grow_the_graph() {
+        let mut world = solid_ground();
+        world.visibility = VisibilityGraph::new(&world);
+        world.place_block(Vector3::new(5 * CHUNK_SIZE, 0, 0), DIRT);
+        assert!(world
+            .visibility
+            .potentially_visible(Vector3::new(5, 0, 0))
+            .is_some());
+    }
+}
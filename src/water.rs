@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Projection,
+    render_target::RenderTarget,
+    texture::{Texture, DEPTH_FORMAT},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaterConfig {
+    /// Height of the waves from trough to crest in blocks, 0.0 keeps the
+    /// surface flat. Default: 0.15
+    pub wave_height: f32,
+    /// Multiplier on how fast the waves and ripples move. Default: 1.0
+    pub wave_speed: f32,
+    /// Colour that deep water fades to, linear RGB. Shallow water is the
+    /// colour of its texture. Default: (0.01, 0.06, 0.15)
+    pub deep_colour: (f32, f32, f32),
+    /// How many blocks of water it takes to hide most of what's behind it.
+    /// Default: 6.0
+    pub clarity: f32,
+}
+
+impl Default for WaterConfig {
+    fn default() -> Self {
+        Self {
+            wave_height: 0.15,
+            wave_speed: 1.0,
+            deep_colour: (0.01, 0.06, 0.15),
+            clarity: 6.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    // A vec3 in WGSL, wave_height fills the rest of its 16 bytes
+    deep_colour: [f32; 3],
+    wave_height: f32,
+    wave_speed: f32,
+    clarity: f32,
+    // The camera's near and far planes, to turn the scene depth back into
+    // distances
+    near: f32,
+    far: f32,
+}
+
+impl WaterUniform {
+    fn new(config: &WaterConfig, projection: &Projection) -> Self {
+        let (near, far) = projection.depth_range();
+        Self {
+            deep_colour: config.deep_colour.into(),
+            wave_height: config.wave_height.max(0.0),
+            wave_speed: config.wave_speed,
+            clarity: config.clarity.max(0.01),
+            near,
+            far,
+        }
+    }
+}
+
+// Everything in bind group 2 of the main shader: the simulation time that
+// animates the water, the look of the water, and a copy of the depth of
+// everything drawn before the translucent blocks so the water can tell how
+// deep it is
+pub struct Water {
+    config: WaterConfig,
+    time_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    scene_depth: Texture,
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    // A multisampled depth texture can't be copied into the scene depth, it's
+    // drawn into it instead
+    resolve_pipeline: wgpu::RenderPipeline,
+    resolve_layout: wgpu::BindGroupLayout,
+    resolve_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Water {
+    pub fn new(
+        device: &wgpu::Device,
+        target: &RenderTarget,
+        config: &WaterConfig,
+        projection: &Projection,
+    ) -> Self {
+        let time_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Time Buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Buffer"),
+            contents: bytemuck::cast_slice(&[WaterUniform::new(config, projection)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            // Waves move the vertices, the rest is in the fragment shader
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform(0),
+                uniform(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("water_layout"),
+        });
+
+        let scene_depth = Texture::create_depth_texture(device, target.size(), 1, "scene_depth");
+        let bind_group =
+            Self::create_bind_group(device, &layout, &time_buffer, &uniform_buffer, &scene_depth);
+
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("depth_resolve_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("depth_resolve.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&resolve_layout],
+            push_constant_ranges: &[],
+        });
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Resolve Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let resolve_bind_group = Self::create_resolve_bind_group(device, &resolve_layout, target);
+
+        Self {
+            config: config.clone(),
+            time_buffer,
+            uniform_buffer,
+            scene_depth,
+            layout,
+            bind_group,
+            resolve_pipeline,
+            resolve_layout,
+            resolve_bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        time_buffer: &wgpu::Buffer,
+        uniform_buffer: &wgpu::Buffer,
+        scene_depth: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: time_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&scene_depth.view),
+                },
+            ],
+            label: Some("water_bind_group"),
+        })
+    }
+
+    // Has to follow the render target, the scene depth is the same size as its
+    // depth texture
+    pub fn resize(&mut self, device: &wgpu::Device, target: &RenderTarget) {
+        self.scene_depth = Texture::create_depth_texture(device, target.size(), 1, "scene_depth");
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.time_buffer,
+            &self.uniform_buffer,
+            &self.scene_depth,
+        );
+        self.resolve_bind_group =
+            Self::create_resolve_bind_group(device, &self.resolve_layout, target);
+    }
+
+    // Only needed when the render target is multisampled
+    fn create_resolve_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        target: &RenderTarget,
+    ) -> Option<wgpu::BindGroup> {
+        (target.sample_count() > 1).then(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.depth.view),
+                }],
+                label: Some("depth_resolve_bind_group"),
+            })
+        })
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, time: Duration, projection: &Projection) {
+        queue.write_buffer(
+            &self.time_buffer,
+            0,
+            bytemuck::cast_slice(&[time.as_secs_f32()]),
+        );
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[WaterUniform::new(&self.config, projection)]),
+        );
+    }
+
+    // Has to run between the passes that draw the opaque and translucent
+    // blocks, the water can't read the depth texture it's being drawn with
+    pub fn copy_scene_depth(&self, encoder: &mut wgpu::CommandEncoder, target: &RenderTarget) {
+        let Some(resolve_bind_group) = &self.resolve_bind_group else {
+            encoder.copy_texture_to_texture(
+                target.depth.texture.as_image_copy(),
+                self.scene_depth.texture.as_image_copy(),
+                self.scene_depth.texture.size(),
+            );
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.scene_depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.resolve_pipeline);
+        render_pass.set_bind_group(0, resolve_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
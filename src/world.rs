@@ -0,0 +1,204 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use cgmath::Vector3;
+
+use crate::{block::*, lighting::LightMap, visibility::VisibilityGraph};
+
+pub const WORLD_SIZE: usize = 1414;
+pub const CHUNK_SIZE: i32 = 16;
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// Offsets to the six neighbours of a block or chunk, opposite directions are
+// next to each other so `i ^ 1` gives the opposite of `i`
+pub const NEIGHBOURS: [Vector3<i32>; 6] = [
+    Vector3::new(1, 0, 0),
+    Vector3::new(-1, 0, 0),
+    Vector3::new(0, 1, 0),
+    Vector3::new(0, -1, 0),
+    Vector3::new(0, 0, 1),
+    Vector3::new(0, 0, -1),
+];
+
+// Everything below this that isn't terrain is filled with water
+pub const SEA_LEVEL: i32 = 85;
+
+// Most chunks are completely air or completely underground, those don't need
+// a block per voxel
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chunk {
+    Uniform(BlockId),
+    Dense(Box<[BlockId; CHUNK_VOLUME]>),
+}
+
+impl Chunk {
+    pub fn index(local: Vector3<i32>) -> usize {
+        (local.x + (local.y + local.z * CHUNK_SIZE) * CHUNK_SIZE) as usize
+    }
+
+    pub fn get(&self, local: Vector3<i32>) -> BlockId {
+        match self {
+            Chunk::Uniform(block) => *block,
+            Chunk::Dense(blocks) => blocks[Self::index(local)],
+        }
+    }
+
+    pub fn set(&mut self, local: Vector3<i32>, block: BlockId) {
+        if let Chunk::Uniform(current) = *self {
+            if current == block {
+                return;
+            }
+            *self = Chunk::Dense(Box::new([current; CHUNK_VOLUME]));
+        }
+        if let Chunk::Dense(blocks) = self {
+            blocks[Self::index(local)] = block;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct World {
+    chunks: HashMap<Vector3<i32>, Chunk>,
+    pub blocks: BlockRegistry,
+    // Both kept up to date by `place_block` but not `set_block`
+    pub light: LightMap,
+    pub visibility: VisibilityGraph,
+    // Bumped by every `place_block`, so whatever is worked out from the blocks
+    // can tell when it's out of date
+    generation: u64,
+}
+
+pub fn chunk_pos(pos: Vector3<i32>) -> Vector3<i32> {
+    pos.map(|c| c.div_euclid(CHUNK_SIZE))
+}
+
+pub fn local_pos(pos: Vector3<i32>) -> Vector3<i32> {
+    pos.map(|c| c.rem_euclid(CHUNK_SIZE))
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Terrain from the same fbm heightmap the instances used to be placed with,
+    // filled with dirt below a layer of grass. Terrain under the sea is covered
+    // in mud instead
+    pub fn generate(seed: u32, size: usize) -> Self {
+        use noise::utils::{NoiseMapBuilder, PlaneMapBuilder};
+        use noise::{Fbm, Perlin};
+        let fbm = Fbm::<Perlin>::new(seed);
+
+        let map = PlaneMapBuilder::<_, 2>::new(&fbm)
+            .set_size(size, size)
+            .build();
+
+        let chunks_per_row = (size as i32 + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let mut world = Self::new();
+        for cz in 0..chunks_per_row {
+            for cx in 0..chunks_per_row {
+                let mut heights = vec![-1; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+                for lz in 0..CHUNK_SIZE {
+                    for lx in 0..CHUNK_SIZE {
+                        let (x, z) = (
+                            (cx * CHUNK_SIZE + lx) as usize,
+                            (cz * CHUNK_SIZE + lz) as usize,
+                        );
+                        if x < size && z < size {
+                            let v = map.get_value(x, z) + 1.006;
+                            heights[(lx + lz * CHUNK_SIZE) as usize] = (v * 100.) as i32;
+                        }
+                    }
+                }
+                world.generate_column(cx, cz, &heights);
+            }
+        }
+        world.light = LightMap::new(&world);
+        world.visibility = VisibilityGraph::new(&world);
+        world
+    }
+
+    fn generate_column(&mut self, cx: i32, cz: i32, heights: &[i32]) {
+        let min = heights.iter().copied().min().unwrap_or(-1);
+        let max = heights.iter().copied().max().unwrap_or(-1);
+
+        let top = max.max(SEA_LEVEL);
+
+        for cy in 0..=top.div_euclid(CHUNK_SIZE) {
+            let base = cy * CHUNK_SIZE;
+            let chunk = if base + CHUNK_SIZE <= min {
+                Chunk::Uniform(DIRT)
+            } else {
+                let mut chunk = Chunk::Uniform(AIR);
+                for lz in 0..CHUNK_SIZE {
+                    for lx in 0..CHUNK_SIZE {
+                        let height = heights[(lx + lz * CHUNK_SIZE) as usize];
+                        for ly in 0..CHUNK_SIZE.min(height.max(SEA_LEVEL) - base + 1) {
+                            let y = base + ly;
+                            let block = match y.cmp(&height) {
+                                Ordering::Less => DIRT,
+                                Ordering::Equal if height < SEA_LEVEL => MUD,
+                                Ordering::Equal => GRASS,
+                                Ordering::Greater => WATER,
+                            };
+                            chunk.set(Vector3::new(lx, ly, lz), block);
+                        }
+                    }
+                }
+                chunk
+            };
+            self.chunks.insert(Vector3::new(cx, cy, cz), chunk);
+        }
+    }
+
+    pub fn chunk(&self, pos: Vector3<i32>) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&Vector3<i32>, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    pub fn get_block(&self, pos: Vector3<i32>) -> BlockId {
+        self.chunks
+            .get(&chunk_pos(pos))
+            .map_or(AIR, |chunk| chunk.get(local_pos(pos)))
+    }
+
+    pub fn set_block(&mut self, pos: Vector3<i32>, block: BlockId) {
+        self.chunks
+            .entry(chunk_pos(pos))
+            .or_insert(Chunk::Uniform(AIR))
+            .set(local_pos(pos), block);
+    }
+
+    // Sets a block, relights around it and updates which faces of its chunk
+    // can see each other
+    pub fn place_block(&mut self, pos: Vector3<i32>, block: BlockId) {
+        self.set_block(pos, block);
+        let mut light = std::mem::take(&mut self.light);
+        light.block_changed(self, pos);
+        self.light = light;
+        let mut visibility = std::mem::take(&mut self.visibility);
+        visibility.update_chunk(self, chunk_pos(pos));
+        self.visibility = visibility;
+        self.generation += 1;
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn block_type(&self, pos: Vector3<i32>) -> &BlockType {
+        self.blocks.get(self.get_block(pos))
+    }
+
+    // There's nothing below the world, so treat it as solid to keep things from
+    // falling forever
+    pub fn is_solid(&self, pos: Vector3<i32>) -> bool {
+        pos.y < 0 || self.block_type(pos).solid
+    }
+
+    pub fn is_opaque(&self, pos: Vector3<i32>) -> bool {
+        pos.y < 0 || self.block_type(pos).opaque
+    }
+}